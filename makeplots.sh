@@ -1,25 +1,23 @@
 today=`date +%m/%d/%Y`
-today_under=`date +%m-%d-%Y`
+datestamp=`date +%Y%m%d`
 cp today.csv yesterday.csv
 wget -q 'https://data.pa.gov/api/views/j72v-r42c/rows.csv?accessType=DOWNLOAD' -O daily_raw.csv
 wget -q 'https://data.pa.gov/api/views/kayn-sjhx/rows.csv?accessType=DOWNLOAD' -O today.csv
 wget -q 'https://data.pa.gov/api/views/bicw-3gwi/rows.csv?accessType=DOWNLOAD' -O pavax.csv
-wget -q 'https://data.pa.gov/api/views/niuh-2xe3/rows.csv?accessType=DOWNLOAD' -O vaxday/${today_under}.csv
-wget -q 'https://data.wprdc.org/dataset/80e0ca5d-c88a-4b1a-bf5d-51d0aaeeac86/resource/4051a85a-bf92-45fc-adc6-b31eb8efaad4/download/covid_19_testing_cases.csv' -O testday/${today_under}.csv
-datestamp=`date +%Y%m%d`
-python3 fix_cases.py > daily.csv
-cp daily_raw.csv pa_data/daily_${datestamp}_raw.csv
-cp daily.csv pa_data/daily_${datestamp}.csv
-cp today.csv pa_data/today_${datestamp}.csv
-#echo -n "yesterday cases total: " 
+wget -q 'https://data.pa.gov/api/views/niuh-2xe3/rows.csv?accessType=DOWNLOAD' -O vaxcoverage.csv
+wget -q 'https://data.wprdc.org/dataset/80e0ca5d-c88a-4b1a-bf5d-51d0aaeeac86/resource/4051a85a-bf92-45fc-adc6-b31eb8efaad4/download/covid_19_testing_cases.csv' -O covid_19_testing_cases.csv
+./target/release/covidreport ingest --dir .
+# The cleaned cases feed, as archived by ingest
+daily=pa_data/daily_${datestamp}.csv
+#echo -n "yesterday cases total: "
 awk -F ',' '{sum += $2} END {print sum}' all_cases.txt
 xsv select 1,2,5,6,13,36 today.csv | xsv search -s 1 Alleg | xsv select 2,3,4,5,6 | csvsort -c 1 | sed 's/,/ /g' | python3 swapdate.py >  plotme
-xsv select 1,2,3 daily.csv | xsv search -s 1 Allegheny | csvsort -c 2 | xsv select 2,3 > all_cases.txt
+xsv select 1,2,3 ${daily} | xsv search -s 1 Allegheny | csvsort -c 2 | xsv select 2,3 > all_cases.txt
 echo -n "today cases total allegheny: "
 awk -F ',' '{sum += $2} END {print sum}' all_cases.txt
 echo -n "yesterday cases total state: "
 awk -F ' ' '{sum += $2} END {print sum}' state_cases.txt
-xsv select 1,2,3 daily.csv | xsv search -s 1 Pennsylvania | csvsort -c 2 | xsv select 2,3  | sed 's/,/ /g' | python3 swapdate.py > state_cases.txt
+xsv select 1,2,3 ${daily} | xsv search -s 1 Pennsylvania | csvsort -c 2 | xsv select 2,3  | sed 's/,/ /g' | python3 swapdate.py > state_cases.txt
 echo -n "today cases total state: "
 awk -F ' ' '{sum += $2} END {print sum}' state_cases.txt
 xsv select 1,2,5,6,13,36 today.csv | xsv search -s 1 Pennsylvania | xsv select 2,3,4,5,6 | csvsort -c 1 | sed 's/,/ /g' | python3 swapdate.py > plotstate
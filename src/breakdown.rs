@@ -0,0 +1,375 @@
+//! Breakdowns of the WPRDC test records by demographic group.
+//!
+//! Cases, and the cases that went to the hospital, the ICU or on a
+//! ventilator, are tallied by report date under each of the demographic
+//! fields in the file.  Any pairing can be plotted as 7 day averages per
+//! group or summed up in a table.  By age, the counts can also be put per
+//! 100k residents of each bucket, with rate ratios against one of them.
+
+use anyhow::{anyhow, Result};
+use plotters::prelude::*;
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::population::{per_100k, AgePopulations};
+use crate::table::{Cell, Format, Table};
+use crate::testrecs::{summarize_tests, Tally};
+use crate::TestRecord;
+
+/// The age buckets of the test records, youngest first
+pub const AGE_BUCKETS: [&str; 8] = [
+    "0 to 9", "10 to 19", "20 to 29", "30 to 39", "40 to 49", "50 to 59", "60 to 69", "70+",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Age,
+    Sex,
+    Race,
+    Ethnicity,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [Field::Age, Field::Sex, Field::Race, Field::Ethnicity];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Age => "age",
+            Field::Sex => "sex",
+            Field::Race => "race",
+            Field::Ethnicity => "ethnicity",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Field::Age => "age group",
+            _ => self.name(),
+        }
+    }
+
+    /// The record's group.  Age buckets are lowercased: the file has had
+    /// both "0 to 9" and "0 To 9".
+    fn key(self, rec: &TestRecord) -> String {
+        match self {
+            Field::Age => rec.age_bucket.to_lowercase(),
+            Field::Sex => rec.sex.to_string(),
+            Field::Race => rec.race.to_string(),
+            Field::Ethnicity => rec.ethnicity.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Field {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Field> {
+        Field::ALL
+            .into_iter()
+            .find(|f| f.name() == s)
+            .ok_or_else(|| anyhow!("unknown field {s:?}; expected age, sex, race or ethnicity"))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Cases,
+    Hospitalized,
+    Icu,
+    Ventilated,
+}
+
+/// The flags are "Y" or "N", or blank when unknown.
+fn flagged(flag: &str) -> bool {
+    flag.eq_ignore_ascii_case("y") || flag.eq_ignore_ascii_case("yes")
+}
+
+impl Outcome {
+    pub const ALL: [Outcome; 4] = [Outcome::Cases, Outcome::Hospitalized, Outcome::Icu, Outcome::Ventilated];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Cases => "cases",
+            Outcome::Hospitalized => "hospitalized",
+            Outcome::Icu => "icu",
+            Outcome::Ventilated => "ventilated",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Outcome::Cases => "Cases",
+            Outcome::Hospitalized => "Hospitalized cases",
+            Outcome::Icu => "ICU cases",
+            Outcome::Ventilated => "Ventilated cases",
+        }
+    }
+
+    /// Whether a test record counts toward this outcome.
+    pub fn applies(self, rec: &TestRecord) -> bool {
+        rec.is_case()
+            && match self {
+                Outcome::Cases => true,
+                Outcome::Hospitalized => flagged(rec.hospital_flag),
+                Outcome::Icu => flagged(rec.icu_flag),
+                Outcome::Ventilated => flagged(rec.vent_flag),
+            }
+    }
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Outcome> {
+        Outcome::ALL
+            .into_iter()
+            .find(|o| o.name() == s)
+            .ok_or_else(|| anyhow!("unknown outcome {s:?}; expected cases, hospitalized, icu or ventilated"))
+    }
+}
+
+/// Daily counts of every outcome by every field.
+#[derive(Debug)]
+pub struct Breakdowns {
+    tallies: HashMap<(Field, Outcome), Tally>,
+}
+
+impl Default for Breakdowns {
+    fn default() -> Self {
+        let tallies = Field::ALL
+            .into_iter()
+            .flat_map(|f| Outcome::ALL.into_iter().map(move |o| ((f, o), Tally::default())))
+            .collect();
+        Breakdowns { tallies }
+    }
+}
+
+impl Breakdowns {
+    pub fn add(&mut self, date: chrono::NaiveDate, rec: &TestRecord) {
+        for outcome in Outcome::ALL {
+            if !outcome.applies(rec) {
+                continue;
+            }
+            for field in Field::ALL {
+                self.tallies.get_mut(&(field, outcome)).unwrap().add(date, &field.key(rec));
+            }
+        }
+    }
+
+    pub fn get(&self, field: Field, outcome: Outcome) -> &Tally {
+        &self.tallies[&(field, outcome)]
+    }
+}
+
+fn avg7(counts: &[u32]) -> f64 {
+    counts.iter().sum::<u32>() as f64 / 7.0
+}
+
+/// Per group: the last 7 days against the 7 before, as totals and a daily
+/// average, and each group's share of the last 7 days and of everything.
+pub fn summary_table(tally: &Tally, title: &str) -> Option<Table> {
+    let (first, last) = tally.date_range()?;
+    let groups: Vec<(&str, Vec<u32>)> = tally.keys().into_iter().map(|k| (k, tally.series(k, first, last))).collect();
+    let n = groups.first().map_or(0, |(_, s)| s.len());
+    let split = |s: &[u32]| (s[n.saturating_sub(7)..].to_vec(), s[n.saturating_sub(14)..n.saturating_sub(7)].to_vec());
+    let week_total: u32 = groups.iter().map(|(_, s)| split(s).0.iter().sum::<u32>()).sum();
+    let all_total: u32 = groups.iter().map(|(_, s)| s.iter().sum::<u32>()).sum();
+
+    let mut table = Table::new(
+        format!("{title}, 7 days to {last} vs. 7 days to {}", last - chrono::Duration::days(7)),
+        &["Group", "Last 7 days", "Prior 7 days", "Change %", "7 day avg", "Share of week %", "Since start", "Share %"],
+    );
+    for (group, series) in &groups {
+        let (this, before) = split(series);
+        let (this_n, before_n): (u32, u32) = (this.iter().sum(), before.iter().sum());
+        let total: u32 = series.iter().sum();
+        let pct = |a: u32, b: u32| (b > 0).then(|| a as f64 * 100.0 / b as f64);
+        table.row(vec![
+            Cell::text(*group),
+            this_n.into(),
+            before_n.into(),
+            pct(this_n, before_n).map(|p| p - 100.0).into(),
+            avg7(&this).into(),
+            pct(this_n, week_total).into(),
+            total.into(),
+            pct(total, all_total).into(),
+        ]);
+    }
+    Some(table)
+}
+
+/// Per age bucket: the last 7 days and the 7 before per 100k residents of
+/// that bucket, and the last 7 days' rate as a ratio to the `reference`
+/// bucket's, or to all ages together if there's none.  Buckets with no
+/// population (e.g. "unknown") are left out.
+pub fn rate_table(tally: &Tally, title: &str, pops: &AgePopulations, reference: Option<&str>) -> Result<Option<Table>> {
+    let (first, last) = match tally.date_range() {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+    let week = |age: &str, end: chrono::NaiveDate| -> u32 {
+        tally.series(age, end - chrono::Duration::days(6), end).iter().sum()
+    };
+    let prior = last - chrono::Duration::days(7);
+    let rates: Vec<(&str, u32, f64, f64)> = tally
+        .keys()
+        .into_iter()
+        .filter_map(|age| {
+            let pop = pops.get(age)?;
+            Some((age, pop, per_100k(week(age, last) as f64, pop), per_100k(week(age, prior) as f64, pop)))
+        })
+        .collect();
+    let (ref_name, ref_rate) = match reference {
+        Some(r) => {
+            let r = r.to_lowercase();
+            let rate = rates.iter().find(|(age, ..)| *age == r).map(|(_, _, rate, _)| *rate);
+            (r.clone(), rate.ok_or_else(|| anyhow!("no population for reference age group {r:?}"))?)
+        }
+        None => {
+            let cases: u32 = rates.iter().map(|(age, ..)| week(age, last)).sum();
+            ("all ages".to_string(), per_100k(cases as f64, rates.iter().map(|(_, pop, ..)| pop).sum()))
+        }
+    };
+
+    let mut table = Table::new(
+        format!("{title} per 100k, 7 days to {last} vs. 7 days to {prior} (since {first})"),
+        &["Group", "Population", "Last 7 days /100k", "Prior 7 days /100k", "Change %", &format!("Ratio to {ref_name}")],
+    );
+    for (age, pop, rate, before) in rates {
+        table.row(vec![
+            Cell::text(age),
+            pop.into(),
+            rate.into(),
+            before.into(),
+            (before > 0.0).then(|| rate * 100.0 / before - 100.0).into(),
+            if ref_rate > 0.0 { Cell::Num(rate / ref_rate, 2) } else { Cell::Missing },
+        ]);
+    }
+    Ok(Some(table))
+}
+
+/// 7 day averages per group, or per 100k residents of each group given
+/// `pops`, leaving out groups it has no population for.  `groups` picks
+/// which groups to draw, in order; otherwise every group in the tally is
+/// drawn.  `max_y` fixes the y axis; otherwise it fits the data.
+pub fn plot(
+    tally: &Tally,
+    title: &str,
+    img_path: &str,
+    groups: Option<&[&str]>,
+    max_y: Option<f64>,
+    pops: Option<&AgePopulations>,
+) -> Result<()> {
+    let (min_date, last_date) = tally
+        .date_range()
+        .ok_or_else(|| anyhow!("nothing to plot for {title}"))?;
+    let max_date = last_date + chrono::Duration::days(1);
+    let dates7day = (6..).map(|x| min_date + chrono::Duration::days(x));
+    let groups: Vec<&str> = match groups {
+        Some(groups) => groups.to_vec(),
+        None => tally.keys().into_iter().collect(),
+    };
+    let lines: Vec<(&str, Vec<f64>)> = groups
+        .into_iter()
+        .filter_map(|group| {
+            let scale = match pops {
+                Some(pops) => 100000.0 / pops.get(group)? as f64,
+                None => 1.0,
+            };
+            let avgs = tally.series(group, min_date, last_date).windows(7).map(|w| avg7(w) * scale).collect();
+            Some((group, avgs))
+        })
+        .collect();
+    let max_y = max_y.unwrap_or_else(|| {
+        let m = lines.iter().flat_map(|(_, v)| v.iter()).fold(1.0, |a: f64, b| a.max(*b));
+        m + m / 20.0
+    });
+
+    let root = BitMapBackend::new(img_path, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption(format!("{title}: Allegheny County"), ("sans-serif", 40))
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart.configure_mesh().x_labels(9).x_desc("Date").draw()?;
+    for (color, (group, avgs)) in lines.into_iter().enumerate() {
+        let style = plotters::style::ShapeStyle {
+            color: plotters::style::Palette99::pick(color).to_rgba(),
+            filled: true,
+            stroke_width: 1,
+        };
+        chart
+            .draw_series(LineSeries::new(dates7day.to_owned().zip(avgs).map(|(d, v)| (d, v.min(max_y))), style.clone()))?
+            .label(group.to_owned())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
+    }
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+/// Title for an outcome broken down by a field, e.g. "Cases by age group and date".
+pub fn title(field: Field, outcome: Outcome) -> String {
+    format!("{} by {} and date", outcome.label(), field.label())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct BreakdownOpt {
+    #[structopt(long, default_value = "age", help = "Group by age, sex, race or ethnicity")]
+    by: Field,
+    #[structopt(long, default_value = "cases", help = "Count cases, hospitalized, icu or ventilated")]
+    outcome: Outcome,
+    #[structopt(long, default_value = "2021-01-01", help = "First report date to include (%Y-%m-%d)")]
+    since: String,
+    #[structopt(long, help = "Rates per 100k residents of each age group (with --by age)")]
+    per_100k: bool,
+    #[structopt(long, help = "Age group to compare rates against; all ages if not given")]
+    reference: Option<String>,
+    #[structopt(long, default_value = "text", help = "Table format: text, csv or json")]
+    format: Format,
+}
+
+/// Print the summary table for one breakdown and plot it to
+/// `<outcome>_by_<field>.png`, or with `--per-100k`, the rate table and
+/// `<outcome>_by_age_per_100k.png`.
+pub fn breakdown(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &BreakdownOpt) -> Result<()> {
+    if opt.per_100k && opt.by != Field::Age {
+        return Err(anyhow!("--per-100k needs --by age; there are no populations by {}", opt.by.name()));
+    }
+    let since = chrono::NaiveDate::parse_from_str(&opt.since, "%Y-%m-%d")?;
+    let pops = if opt.per_100k { Some(AgePopulations::load(cfg)?) } else { None };
+    let summary = summarize_tests(cfg, today, since)?;
+    let tally = summary.groups.get(opt.by, opt.outcome);
+    let title = title(opt.by, opt.outcome);
+    let table = match &pops {
+        Some(pops) => rate_table(tally, &title, pops, opt.reference.as_deref())?,
+        None => summary_table(tally, &title),
+    };
+    match table {
+        Some(table) => table.print(opt.format)?,
+        None => return Err(anyhow!("no {} in the test records since {since}", opt.outcome.name())),
+    }
+    match &pops {
+        Some(pops) => plot(
+            tally,
+            &format!("{title}, per 100k"),
+            &format!("{}_by_age_per_100k.png", opt.outcome.name()),
+            None,
+            None,
+            Some(pops),
+        ),
+        None => plot(
+            tally,
+            &title,
+            &format!("{}_by_{}.png", opt.outcome.name(), opt.by.name()),
+            None,
+            None,
+            None,
+        ),
+    }
+}
@@ -0,0 +1,224 @@
+//! Run configuration: where the data lives, how the archived files are named,
+//! and which jurisdictions make it into the daily report.
+//!
+//! The config is a TOML file.  Every key is optional; anything left out falls
+//! back to the defaults below, which match the original layout on Dave's
+//! machine.  See `covidreport.example.toml` for a fully spelled-out example.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+use crate::gaps::GapPolicy;
+use crate::growth::GrowthOptions;
+use crate::icu::IcuOptions;
+use crate::levels::RuleSet;
+use crate::nowcast::NowcastOptions;
+use crate::parse::ParseOptions;
+use crate::ratio::RatioOptions;
+use crate::reportlag::ReportLagOptions;
+use crate::rt::RtOptions;
+use crate::schema::Aliases;
+use crate::weekday::WeekdayOptions;
+
+const CONFIG_ENV: &str = "COVIDREPORT_CONFIG";
+const LOCAL_CONFIG: &str = "covidreport.toml";
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Directory holding the dated PA snapshots (`daily_*.csv`, `today_*.csv`, ...)
+    pub csv_dir: PathBuf,
+    /// Directory holding the dated WPRDC test record downloads
+    pub tests_dir: PathBuf,
+    /// Directory holding the dated cumulative vaccination snapshots
+    pub vax_dir: PathBuf,
+    /// SQLite snapshot store (default: `covidreport.sqlite` in `csv_dir`)
+    pub store: Option<PathBuf>,
+    /// Name of the statewide jurisdiction in the PA feeds
+    pub state: String,
+    /// CSV of `Jurisdiction,Population` overriding the built-in 2020 Census table
+    pub populations: Option<PathBuf>,
+    /// CSV of `Age,Population` per WPRDC age bucket, replacing the built-in Allegheny table
+    pub age_populations: Option<PathBuf>,
+    pub files: FilePatterns,
+    /// How to treat CSV rows that don't parse
+    pub parse: ParseOptions,
+    /// Alternate upstream names for expected columns, per feed
+    pub aliases: Aliases,
+    /// How to fill in missing hospitalization and ICU values
+    pub gap_policy: GapPolicy,
+    /// Reference window for the normal ICU load
+    pub icu: IcuOptions,
+    /// Risk level definitions added to, or replacing, the built-in CDC ones
+    pub level_rules: Vec<RuleSet>,
+    /// Correction of the undercounted recent days of the cases feed
+    pub nowcast: NowcastOptions,
+    /// Correction of the day-of-week reporting pattern of the cases feed
+    pub weekday: WeekdayOptions,
+    /// Serial interval and window for the Rt estimate
+    pub rt: RtOptions,
+    /// Window for the growth rates and doubling times
+    pub growth: GrowthOptions,
+    /// Lag for the census-per-case ratios
+    pub ratio: RatioOptions,
+    /// When the daily report warns about collection-to-report lags
+    pub report_lag: ReportLagOptions,
+    /// Jurisdictions to report on, in the order they are printed
+    pub jurisdictions: Vec<Jurisdiction>,
+}
+
+/// File names for each archived feed, as `strftime` patterns of the vintage date.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilePatterns {
+    pub cases: String,
+    /// The cases feed as downloaded, before `ingest` cleans it up
+    pub cases_raw: String,
+    pub hospitalizations: String,
+    pub pcr_tests: String,
+    pub tests: String,
+    pub vax_doses: String,
+    pub vax_coverage: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Jurisdiction {
+    /// Name as it appears in the feeds, e.g. "Allegheny"
+    pub name: String,
+    /// Name used for headings and plot file names, e.g. "Allegheny County"
+    pub display_name: Option<String>,
+    /// Overrides the built-in Census population, for per-capita figures
+    pub population: Option<u32>,
+    /// Print a `## display name` section heading before the stats
+    #[serde(default)]
+    pub heading: bool,
+    /// Emit a `{{ shortcode() }}` line after the stats for the site generator
+    pub shortcode: Option<String>,
+}
+
+impl Jurisdiction {
+    pub fn display_name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            csv_dir: PathBuf::from("/home/dga/pa_data"),
+            tests_dir: PathBuf::from("/home/dga/testday"),
+            vax_dir: PathBuf::from("/home/dga/vaxday"),
+            store: None,
+            state: "Pennsylvania".to_string(),
+            populations: None,
+            age_populations: None,
+            files: FilePatterns::default(),
+            parse: ParseOptions::default(),
+            aliases: Aliases::new(),
+            gap_policy: GapPolicy::default(),
+            icu: IcuOptions::default(),
+            level_rules: Vec::new(),
+            nowcast: NowcastOptions::default(),
+            weekday: WeekdayOptions::default(),
+            rt: RtOptions::default(),
+            growth: GrowthOptions::default(),
+            ratio: RatioOptions::default(),
+            report_lag: ReportLagOptions::default(),
+            jurisdictions: vec![
+                Jurisdiction {
+                    name: "Allegheny".to_string(),
+                    display_name: Some("Allegheny County".to_string()),
+                    population: None,
+                    heading: true,
+                    shortcode: Some("allegheny".to_string()),
+                },
+                Jurisdiction {
+                    name: "Pennsylvania".to_string(),
+                    display_name: None,
+                    population: None,
+                    heading: true,
+                    shortcode: Some("pennsylvania".to_string()),
+                },
+                Jurisdiction {
+                    name: "Philadelphia".to_string(),
+                    display_name: Some("Philadelphia County".to_string()),
+                    population: None,
+                    heading: false,
+                    shortcode: None,
+                },
+            ],
+        }
+    }
+}
+
+impl Default for FilePatterns {
+    fn default() -> Self {
+        FilePatterns {
+            cases: "daily_%Y%m%d.csv".to_string(),
+            cases_raw: "daily_%Y%m%d_raw.csv".to_string(),
+            hospitalizations: "today_%Y%m%d.csv".to_string(),
+            pcr_tests: "pcr_test_counts_%Y%m%d.csv".to_string(),
+            tests: "%m-%d-%Y.csv".to_string(),
+            vax_doses: "pavax_%Y%m%d.csv".to_string(),
+            vax_coverage: "%m-%d-%Y.csv".to_string(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from `explicit` if given, otherwise from the first file
+    /// found on the search path, otherwise use the built-in defaults.
+    ///
+    /// Search order: `$COVIDREPORT_CONFIG`, `./covidreport.toml`,
+    /// `$XDG_CONFIG_HOME/covidreport/config.toml`, `~/.config/covidreport/config.toml`.
+    pub fn load(explicit: Option<&Path>) -> Result<Config> {
+        if let Some(path) = explicit {
+            return Config::from_file(path);
+        }
+        match search_path().into_iter().find(|p| p.is_file()) {
+            Some(path) => Config::from_file(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        let cfg: Config = toml::from_str(&text).with_context(|| format!("parsing config file {}", path.display()))?;
+        cfg.validate().with_context(|| format!("checking config file {}", path.display()))?;
+        Ok(cfg)
+    }
+
+    /// Options that parse but can't be used.
+    fn validate(&self) -> Result<()> {
+        self.rt.validate()
+    }
+
+    pub fn store_path(&self) -> PathBuf {
+        self.store
+            .clone()
+            .unwrap_or_else(|| self.csv_dir.join("covidreport.sqlite"))
+    }
+}
+
+fn search_path() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if let Some(p) = std::env::var_os(CONFIG_ENV) {
+        paths.push(PathBuf::from(p));
+    }
+    paths.push(PathBuf::from(LOCAL_CONFIG));
+    if let Some(xdg) = std::env::var_os("XDG_CONFIG_HOME") {
+        paths.push(PathBuf::from(xdg).join("covidreport").join("config.toml"));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        paths.push(
+            PathBuf::from(home)
+                .join(".config")
+                .join("covidreport")
+                .join("config.toml"),
+        );
+    }
+    paths
+}
@@ -0,0 +1,198 @@
+//! Epidemic curve: Allegheny cases by the date the test was collected.
+//!
+//! Collection dates are closer to when people got sick than report dates,
+//! but the latest days keep filling in as results come back.  The days
+//! within the 90th percentile reporting lag of cases, going by the last few
+//! weeks of reports, are shaded as provisional.
+
+use anyhow::{anyhow, Result};
+use plotters::prelude::*;
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::reportlag::ReportLags;
+use crate::table::{Cell, Format, Table};
+use crate::testrecs::{fold_test_records, Tally};
+
+/// Case statuses, stacked from the bottom in this order
+const STATUSES: [&str; 2] = ["Confirmed", "Probable"];
+
+#[derive(Debug, StructOpt)]
+pub struct EpicurveOpt {
+    #[structopt(long, default_value = "2021-01-01", help = "First collection date to include (%Y-%m-%d)")]
+    since: String,
+    #[structopt(long, default_value = "4", help = "Weeks of reports to take the reporting lag from")]
+    lag_weeks: u32,
+    #[structopt(long, help = "Days to shade as provisional, instead of the observed lag")]
+    provisional_days: Option<u32>,
+    #[structopt(long, default_value = "text", help = "Table format: text, csv or json")]
+    format: Format,
+}
+
+/// Cases by collection date and status, with the provisional window.
+pub struct Epicurve {
+    cases: Tally,
+    /// Latest report date in the file; nothing collected after it is known
+    pub last: chrono::NaiveDate,
+    /// Days up to and including `last` whose counts are still incomplete
+    pub provisional_days: u32,
+}
+
+impl Epicurve {
+    pub fn load(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &EpicurveOpt) -> Result<Epicurve> {
+        let since = chrono::NaiveDate::parse_from_str(&opt.since, "%Y-%m-%d")?;
+        if opt.lag_weeks == 0 {
+            return Err(anyhow!("--lag-weeks must be at least 1"));
+        }
+        let mut cases = Tally::default();
+        let mut lags = ReportLags::default();
+        fold_test_records(cfg, today, |rec| {
+            if rec.is_case() {
+                lags.add(rec);
+                if rec.collection_date >= since {
+                    cases.add(rec.collection_date, rec.case_status);
+                }
+            }
+        })?;
+        let last = lags.last_date().ok_or_else(|| anyhow!("no cases in the test records"))?;
+        let provisional_days = match opt.provisional_days {
+            Some(days) => days,
+            None => {
+                let from = last - chrono::Duration::weeks(opt.lag_weeks.into()) + chrono::Duration::days(1);
+                // A lag of n days leaves the last n+1 collection dates short
+                lags.stats(&STATUSES, from, last).map_or(0, |s| s.p90 + 1)
+            }
+        };
+        Ok(Epicurve {
+            cases,
+            last,
+            provisional_days,
+        })
+    }
+
+    /// First collection date with cases.
+    fn first(&self) -> Option<chrono::NaiveDate> {
+        Some(self.cases.date_range()?.0)
+    }
+
+    pub fn provisional_from(&self) -> chrono::NaiveDate {
+        self.last - chrono::Duration::days(i64::from(self.provisional_days) - 1)
+    }
+
+    /// Daily cases per status from the first collection date through `last`.
+    fn series(&self) -> Vec<[u32; 2]> {
+        let first = match self.first() {
+            Some(first) => first,
+            None => return Vec::new(),
+        };
+        let by_status = STATUSES.map(|s| self.cases.series(s, first, self.last));
+        (0..by_status[0].len()).map(|i| [by_status[0][i], by_status[1][i]]).collect()
+    }
+
+    /// The last four weeks, day by day, marking the provisional ones.
+    pub fn table(&self) -> Table {
+        let mut table = Table::new(
+            format!("Cases by collection date, reports to {}", self.last),
+            &["Collected", "Confirmed", "Probable", "Total", "Provisional"],
+        );
+        let first = match self.first() {
+            Some(first) => first,
+            None => return table,
+        };
+        let series = self.series();
+        for (i, [confirmed, probable]) in series.iter().enumerate().skip(series.len().saturating_sub(28)) {
+            let date = first + chrono::Duration::days(i as i64);
+            table.row(vec![
+                Cell::text(date.to_string()),
+                (*confirmed).into(),
+                (*probable).into(),
+                (confirmed + probable).into(),
+                Cell::text(if date >= self.provisional_from() { "yes" } else { "" }),
+            ]);
+        }
+        table
+    }
+}
+
+/// Stacked daily bars by status, the 7 day average of the total, and the
+/// provisional days shaded, to `epicurve.png`.
+pub fn plot(curve: &Epicurve) -> Result<()> {
+    let min_date = curve.first().ok_or_else(|| anyhow!("no cases to plot"))?;
+    let max_date = curve.last + chrono::Duration::days(1);
+    let series = curve.series();
+    let dates = || (0..).map(|d| min_date + chrono::Duration::days(d));
+    let max_y = series.iter().map(|[c, p]| c + p).fold(10, u32::max) as f64 * 1.05;
+
+    let root = BitMapBackend::new("epicurve.png", (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let shade_style = plotters::style::ShapeStyle {
+        color: BLACK.mix(0.12).to_rgba(),
+        filled: true,
+        stroke_width: 0,
+    };
+    let avg_style = plotters::style::ShapeStyle {
+        color: BLACK.mix(0.8).to_rgba(),
+        filled: true,
+        stroke_width: 2,
+    };
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption("Cases by collection date: Allegheny County", ("sans-serif", 40))
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .x_labels(9)
+        .x_desc("Date collected")
+        .y_desc("Cases")
+        .draw()?;
+    if curve.provisional_days > 0 {
+        chart
+            .draw_series(std::iter::once(Rectangle::new(
+                [(curve.provisional_from().max(min_date), 0.0), (max_date, max_y)],
+                shade_style.clone(),
+            )))?
+            .label(format!("Provisional (last {} days)", curve.provisional_days))
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], shade_style.clone()));
+    }
+    for (i, status) in STATUSES.iter().enumerate() {
+        let style = plotters::style::ShapeStyle {
+            color: plotters::style::Palette99::pick(i).mix(0.6).to_rgba(),
+            filled: true,
+            stroke_width: 0,
+        };
+        let bars = dates().zip(&series).map(|(d, counts)| {
+            let below: u32 = counts[..i].iter().sum();
+            Rectangle::new(
+                [(d, below as f64), (d + chrono::Duration::days(1), (below + counts[i]) as f64)],
+                style.clone(),
+            )
+        });
+        chart
+            .draw_series(bars)?
+            .label(*status)
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], style.clone()));
+    }
+    let avgs = series
+        .windows(7)
+        .map(|w| w.iter().map(|[c, p]| c + p).sum::<u32>() as f64 / 7.0);
+    chart
+        .draw_series(LineSeries::new(dates().skip(6).zip(avgs), avg_style.clone()))?
+        .label("7 day average")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], avg_style.clone()));
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+/// Print the recent days of the curve and plot it.
+pub fn epicurve(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &EpicurveOpt) -> Result<()> {
+    let curve = Epicurve::load(cfg, today, opt)?;
+    curve.table().print(opt.format)?;
+    plot(&curve)
+}
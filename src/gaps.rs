@@ -0,0 +1,182 @@
+//! Filling in missing values in the daily hospitalization series.
+//!
+//! The hospital feed skips a county now and then.  Which policy is right
+//! depends on what the series is used for, so it's configurable, and every
+//! filled-in point is flagged so the plots can show it for what it is.
+
+use anyhow::anyhow;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GapPolicy {
+    /// Leave gaps empty; plots break the line
+    None,
+    /// Interpolate linearly between the observations either side of a gap
+    #[default]
+    Linear,
+    /// Carry the last observation forward
+    Locf,
+}
+
+impl GapPolicy {
+    pub fn name(self) -> &'static str {
+        match self {
+            GapPolicy::None => "none",
+            GapPolicy::Linear => "linear",
+            GapPolicy::Locf => "locf",
+        }
+    }
+}
+
+impl std::str::FromStr for GapPolicy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> anyhow::Result<GapPolicy> {
+        match s {
+            "none" => Ok(GapPolicy::None),
+            "linear" => Ok(GapPolicy::Linear),
+            "locf" => Ok(GapPolicy::Locf),
+            _ => Err(anyhow!("unknown gap policy {s:?}; expected none, linear or locf")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    /// None when the value is missing and the policy can't fill it
+    pub value: Option<u32>,
+    /// The value was filled in rather than reported
+    pub imputed: bool,
+}
+
+/// Fill the gaps in `vals` according to `policy`.  Gaps at the start of the
+/// series (and, for `linear`, at the end) have nothing to fill from and stay
+/// empty rather than dropping to zero.
+pub fn fill<I: Iterator<Item = Option<u32>>>(policy: GapPolicy, vals: I) -> Vec<Point> {
+    let vals: Vec<Option<u32>> = vals.collect();
+    let mut points: Vec<Point> = vals
+        .iter()
+        .map(|&value| Point { value, imputed: false })
+        .collect();
+    match policy {
+        GapPolicy::None => {}
+        GapPolicy::Locf => {
+            let mut last = None;
+            for p in &mut points {
+                match p.value {
+                    Some(v) => last = Some(v),
+                    None if last.is_some() => {
+                        p.value = last;
+                        p.imputed = true;
+                    }
+                    None => {}
+                }
+            }
+        }
+        GapPolicy::Linear => {
+            let known: Vec<(usize, u32)> = vals
+                .iter()
+                .enumerate()
+                .filter_map(|(i, v)| v.map(|v| (i, v)))
+                .collect();
+            for pair in known.windows(2) {
+                let ((i0, v0), (i1, v1)) = (pair[0], pair[1]);
+                for (i, p) in points.iter_mut().enumerate().take(i1).skip(i0 + 1) {
+                    let t = (i - i0) as f64 / (i1 - i0) as f64;
+                    p.value = Some((v0 as f64 + (v1 as f64 - v0 as f64) * t).round() as u32);
+                    p.imputed = true;
+                }
+            }
+        }
+    }
+    points
+}
+
+/// Split a filled series into runs of consecutive values, so a line can be
+/// drawn for each without bridging the gaps that remain.
+pub fn runs<T: Copy>(xs: &[T], points: &[Point]) -> Vec<Vec<(T, u32)>> {
+    let mut runs = vec![];
+    let mut run = vec![];
+    for (x, p) in xs.iter().zip(points) {
+        match p.value {
+            Some(v) => run.push((*x, v)),
+            None if !run.is_empty() => runs.push(std::mem::take(&mut run)),
+            None => {}
+        }
+    }
+    if !run.is_empty() {
+        runs.push(run);
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(policy: GapPolicy, vals: &[Option<u32>]) -> Vec<Option<u32>> {
+        fill(policy, vals.iter().copied()).iter().map(|p| p.value).collect()
+    }
+
+    fn imputed(policy: GapPolicy, vals: &[Option<u32>]) -> Vec<bool> {
+        fill(policy, vals.iter().copied()).iter().map(|p| p.imputed).collect()
+    }
+
+    const INNER: [Option<u32>; 5] = [Some(10), None, None, Some(40), Some(50)];
+    const LEADING: [Option<u32>; 4] = [None, None, Some(7), Some(8)];
+    const TRAILING: [Option<u32>; 4] = [Some(7), Some(8), None, None];
+    const MISSING: [Option<u32>; 3] = [None, None, None];
+
+    #[test]
+    fn none_leaves_gaps() {
+        for vals in [&INNER[..], &LEADING, &TRAILING, &MISSING] {
+            assert_eq!(filled(GapPolicy::None, vals), vals);
+            assert!(imputed(GapPolicy::None, vals).iter().all(|i| !i));
+        }
+    }
+
+    #[test]
+    fn linear_interpolates_inner_gaps() {
+        assert_eq!(filled(GapPolicy::Linear, &INNER), [Some(10), Some(20), Some(30), Some(40), Some(50)]);
+        assert_eq!(imputed(GapPolicy::Linear, &INNER), [false, true, true, false, false]);
+    }
+
+    #[test]
+    fn linear_leaves_leading_and_trailing_gaps() {
+        assert_eq!(filled(GapPolicy::Linear, &LEADING), LEADING);
+        assert_eq!(filled(GapPolicy::Linear, &TRAILING), TRAILING);
+        assert!(imputed(GapPolicy::Linear, &TRAILING).iter().all(|i| !i));
+    }
+
+    #[test]
+    fn locf_carries_forward() {
+        assert_eq!(filled(GapPolicy::Locf, &INNER), [Some(10), Some(10), Some(10), Some(40), Some(50)]);
+        assert_eq!(filled(GapPolicy::Locf, &TRAILING), [Some(7), Some(8), Some(8), Some(8)]);
+        assert_eq!(imputed(GapPolicy::Locf, &TRAILING), [false, false, true, true]);
+    }
+
+    #[test]
+    fn locf_leaves_leading_gaps() {
+        assert_eq!(filled(GapPolicy::Locf, &LEADING), LEADING);
+        assert_eq!(imputed(GapPolicy::Locf, &LEADING), [false; 4]);
+    }
+
+    #[test]
+    fn all_missing_stays_missing() {
+        for policy in [GapPolicy::None, GapPolicy::Linear, GapPolicy::Locf] {
+            assert_eq!(filled(policy, &MISSING), MISSING);
+            assert_eq!(imputed(policy, &MISSING), [false; 3]);
+        }
+        assert!(fill(GapPolicy::Linear, std::iter::empty()).is_empty());
+    }
+
+    /// Gaps never turn into a sentinel like 0 or u32::MAX.
+    #[test]
+    fn no_sentinel_values() {
+        for policy in [GapPolicy::None, GapPolicy::Linear, GapPolicy::Locf] {
+            for vals in [&INNER[..], &LEADING, &TRAILING, &MISSING] {
+                assert!(filled(policy, vals).iter().flatten().all(|v| *v != 0 && *v != u32::MAX));
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! Exponential growth rates and doubling or halving times.
+//!
+//! A straight line fitted to the log of a series over the last few weeks
+//! gives the daily growth rate; its standard error gives a confidence
+//! interval, which carries over to the doubling time.  Cases are fitted as a
+//! 7 day average to get rid of the weekday pattern; neighbouring averages
+//! share most of their days, so that interval is on the narrow side.
+
+use serde::Deserialize;
+
+use crate::rt::Z_95;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct GrowthOptions {
+    /// Days to fit over
+    pub window: usize,
+}
+
+impl Default for GrowthOptions {
+    fn default() -> Self {
+        GrowthOptions { window: 14 }
+    }
+}
+
+/// Two-sided 95% quantiles of Student's t for 1 to 30 degrees of freedom.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160, 2.145, 2.131, 2.120,
+    2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042,
+];
+
+/// Two-sided 95% quantile of Student's t with `df` degrees of freedom: from
+/// the table up to 30, where the Cornish-Fisher expansion around the normal
+/// takes over (it's off by a lot for the few points a gappy window leaves).
+fn t_95(df: usize) -> f64 {
+    if let Some(t) = df.checked_sub(1).and_then(|i| T_95.get(i)) {
+        return *t;
+    }
+    let z = Z_95;
+    let df = df as f64;
+    z + (z.powi(3) + z) / (4.0 * df) + (5.0 * z.powi(5) + 16.0 * z.powi(3) + 3.0 * z) / (96.0 * df * df)
+}
+
+/// Daily growth rate of a series (as a log-slope) with its 95% confidence
+/// interval.
+#[derive(Debug, Clone, Copy)]
+pub struct Growth {
+    pub rate: f64,
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Growth {
+    /// Fit the last `window` values of `series`.  Non-positive values can't
+    /// be logged and are left out; None if fewer than 3 points remain.
+    pub fn fit(series: &[f64], window: usize) -> Option<Growth> {
+        let start = series.len().saturating_sub(window);
+        let points: Vec<(f64, f64)> = series[start..]
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| **v > 0.0)
+            .map(|(t, v)| (t as f64, v.ln()))
+            .collect();
+        let n = points.len() as f64;
+        if points.len() < 3 {
+            return None;
+        }
+        let mean_t = points.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+        let stt: f64 = points.iter().map(|(t, _)| (t - mean_t).powi(2)).sum();
+        let sty: f64 = points.iter().map(|(t, y)| (t - mean_t) * (y - mean_y)).sum();
+        let rate = sty / stt;
+        let intercept = mean_y - rate * mean_t;
+        let sse: f64 = points.iter().map(|(t, y)| (y - intercept - rate * t).powi(2)).sum();
+        let se = (sse / (n - 2.0) / stt).sqrt();
+        let half = t_95(points.len() - 2) * se;
+        Some(Growth {
+            rate,
+            lo: rate - half,
+            hi: rate + half,
+        })
+    }
+}
+
+impl std::fmt::Display for Growth {
+    /// "growing 3.1%/day, doubling every 22 days (95% CI 19-28)", or
+    /// "flat" when the interval takes in zero.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pct = |r: f64| (r.exp() - 1.0) * 100.0;
+        let days = |r: f64| std::f64::consts::LN_2 / r.abs();
+        if self.lo <= 0.0 && self.hi >= 0.0 {
+            write!(
+                f,
+                "flat, {:+.1}%/day (95% CI {:+.1} to {:+.1}%)",
+                pct(self.rate),
+                pct(self.lo),
+                pct(self.hi)
+            )
+        } else if self.rate > 0.0 {
+            write!(
+                f,
+                "growing {:.1}%/day, doubling every {:.0} days (95% CI {:.0}-{:.0})",
+                pct(self.rate),
+                days(self.rate),
+                days(self.hi),
+                days(self.lo)
+            )
+        } else {
+            write!(
+                f,
+                "shrinking {:.1}%/day, halving every {:.0} days (95% CI {:.0}-{:.0})",
+                -pct(self.rate),
+                days(self.rate),
+                days(self.lo),
+                days(self.hi)
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exponential(rate: f64, days: usize) -> Vec<f64> {
+        (0..days).map(|t| 100.0 * (rate * t as f64).exp()).collect()
+    }
+
+    #[test]
+    fn exact_exponential_has_a_zero_width_interval() {
+        let g = Growth::fit(&exponential(0.05, 30), 14).unwrap();
+        assert!((g.rate - 0.05).abs() < 1e-12);
+        assert!((g.hi - g.lo).abs() < 1e-9, "{g:?}");
+    }
+
+    #[test]
+    fn interval_is_t_times_the_slope_error() {
+        // Alternating noise around a 3%/day rise
+        let series: Vec<f64> = exponential(0.03, 14)
+            .iter()
+            .enumerate()
+            .map(|(t, v)| v * if t % 2 == 0 { 1.1 } else { 0.9 })
+            .collect();
+        let g = Growth::fit(&series, 14).unwrap();
+        assert!(g.lo < 0.03 && 0.03 < g.hi, "{g:?}");
+        assert!((g.rate - (g.lo + g.hi) / 2.0).abs() < 1e-12);
+
+        let n = 14.0;
+        let ys: Vec<f64> = series.iter().map(|v| v.ln()).collect();
+        let mean_t = (n - 1.0) / 2.0;
+        let stt: f64 = (0..14).map(|t| (t as f64 - mean_t).powi(2)).sum();
+        let intercept = ys.iter().sum::<f64>() / n - g.rate * mean_t;
+        let sse: f64 = ys.iter().enumerate().map(|(t, y)| (y - intercept - g.rate * t as f64).powi(2)).sum();
+        let se = (sse / (n - 2.0) / stt).sqrt();
+        assert!(((g.hi - g.lo) / 2.0 - 2.179 * se).abs() < 1e-12, "{g:?}");
+    }
+
+    #[test]
+    fn fits_only_the_window_and_positive_values() {
+        let mut series = exponential(-0.2, 20);
+        series.extend(exponential(0.04, 10));
+        assert!((Growth::fit(&series, 10).unwrap().rate - 0.04).abs() < 1e-12);
+        series[25] = 0.0;
+        assert!((Growth::fit(&series, 10).unwrap().rate - 0.04).abs() < 1e-12);
+        assert!(Growth::fit(&[0.0, 5.0, 0.0, 6.0], 4).is_none());
+    }
+
+    #[test]
+    fn describes_the_direction() {
+        let flat = Growth { rate: 0.01, lo: -0.01, hi: 0.03 };
+        assert!(flat.to_string().starts_with("flat"), "{flat}");
+        let growing = Growth::fit(&exponential(0.05, 14), 14).unwrap();
+        assert!(growing.to_string().starts_with("growing 5.1%/day, doubling every 14 days"), "{growing}");
+        let shrinking = Growth::fit(&exponential(-0.05, 14), 14).unwrap();
+        assert!(shrinking.to_string().starts_with("shrinking 4.9%/day, halving every 14 days"), "{shrinking}");
+    }
+
+    #[test]
+    fn t_quantile_from_table_then_expansion() {
+        assert_eq!(t_95(1), 12.706);
+        assert_eq!(t_95(12), 2.179);
+        assert_eq!(t_95(30), 2.042);
+        // The expansion picks up where the table leaves off
+        assert!((t_95(31) - 2.040).abs() < 0.001, "{}", t_95(31));
+        assert!((t_95(120) - 1.980).abs() < 0.001, "{}", t_95(120));
+    }
+}
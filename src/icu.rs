@@ -0,0 +1,69 @@
+//! ICU capacity baselines.
+//!
+//! "% full" compares today's COVID ICU census against what the ICUs normally
+//! hold: the jurisdiction's average staffed adult ICU beds and free beds over
+//! a reference window, taken from the hospital feed itself.
+
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::HospitalRecord;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct IcuOptions {
+    /// First and last dates of the reference window
+    pub baseline_from: chrono::NaiveDate,
+    pub baseline_to: chrono::NaiveDate,
+}
+
+impl Default for IcuOptions {
+    fn default() -> Self {
+        IcuOptions {
+            baseline_from: chrono::NaiveDate::from_ymd(2021, 6, 1),
+            baseline_to: chrono::NaiveDate::from_ymd(2021, 7, 31),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Baseline {
+    /// Average staffed adult ICU beds over the window
+    pub total: f64,
+    /// Average free adult ICU beds over the window
+    pub available: f64,
+}
+
+impl Baseline {
+    /// Average capacity over the configured window, from one jurisdiction's
+    /// records.  None if the window has no days with both figures.
+    pub fn from_records(cfg: &Config, recs: &[HospitalRecord]) -> Option<Baseline> {
+        let days: Vec<(u32, u32)> = recs
+            .iter()
+            .filter(|r| (cfg.icu.baseline_from..=cfg.icu.baseline_to).contains(&r.date))
+            .filter_map(|r| Some((r.adult_icu_beds_total?, r.adult_icu_beds_available?)))
+            .filter(|(total, _)| *total > 0)
+            .collect();
+        if days.is_empty() {
+            return None;
+        }
+        let n = days.len() as f64;
+        Some(Baseline {
+            total: days.iter().map(|(t, _)| *t as f64).sum::<f64>() / n,
+            available: days.iter().map(|(_, a)| *a as f64).sum::<f64>() / n,
+        })
+    }
+
+    /// Percent full if the non-COVID load were at its baseline level and
+    /// `covid_icu` patients were added to it.
+    pub fn normalized_full(&self, covid_icu: u32) -> f64 {
+        (self.total - self.available + covid_icu as f64) * 100.0 / self.total
+    }
+}
+
+/// Percent of staffed adult ICU beds in use on the day of `rec`.
+pub fn actual_full(rec: &HospitalRecord) -> Option<f64> {
+    let total = rec.adult_icu_beds_total.filter(|t| *t > 0)?;
+    let available = rec.adult_icu_beds_available?;
+    Some(total.saturating_sub(available) as f64 * 100.0 / total as f64)
+}
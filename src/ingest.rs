@@ -0,0 +1,328 @@
+//! `covidreport ingest`: archive the day's raw downloads under the dated names
+//! the report expects, cleaning up the cases feed on the way.
+//!
+//! This replaces the fix_cases.py and copying steps of makeplots.sh; the
+//! script still fetches the files and derives its plain-text extracts
+//! (plotme, all_cases.txt, 7dayvax, ...) from what this archives.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::parse::csvrecs;
+use crate::schema::canonical_headers;
+use crate::store::Store;
+use crate::{vintage_date, CasesRecord, HospitalRecord};
+use crate::{
+    cases_file, cases_raw_file, hosps_file, pcr_tests_file, tests_file, vax_coverage_file,
+    vax_doses_file,
+};
+
+#[derive(Debug, StructOpt)]
+pub struct IngestOpt {
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Directory holding the raw downloads (default: current directory)"
+    )]
+    dir: Option<PathBuf>,
+    #[structopt(
+        long,
+        help = "Read a single feed from stdin instead (cases, hospitals, pcr-tests, tests, vax-doses, vax-coverage)"
+    )]
+    stdin: Option<Feed>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feed {
+    Cases,
+    Hospitals,
+    PcrTests,
+    Tests,
+    VaxDoses,
+    VaxCoverage,
+}
+
+impl Feed {
+    const ALL: [Feed; 6] = [
+        Feed::Cases,
+        Feed::Hospitals,
+        Feed::PcrTests,
+        Feed::Tests,
+        Feed::VaxDoses,
+        Feed::VaxCoverage,
+    ];
+
+    /// Name makeplots.sh downloads this feed to.
+    fn download_name(self) -> &'static str {
+        match self {
+            Feed::Cases => "daily_raw.csv",
+            Feed::Hospitals => "today.csv",
+            Feed::PcrTests => "pcr_test_counts.csv",
+            Feed::Tests => "covid_19_testing_cases.csv",
+            Feed::VaxDoses => "pavax.csv",
+            Feed::VaxCoverage => "vaxcoverage.csv",
+        }
+    }
+}
+
+impl std::str::FromStr for Feed {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Feed> {
+        match s {
+            "cases" => Ok(Feed::Cases),
+            "hospitals" => Ok(Feed::Hospitals),
+            "pcr-tests" => Ok(Feed::PcrTests),
+            "tests" => Ok(Feed::Tests),
+            "vax-doses" => Ok(Feed::VaxDoses),
+            "vax-coverage" => Ok(Feed::VaxCoverage),
+            _ => Err(anyhow!("unknown feed {s:?}")),
+        }
+    }
+}
+
+pub fn ingest(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &IngestOpt) -> Result<()> {
+    if let Some(feed) = opt.stdin {
+        let mut store = Store::open(cfg)?;
+        let mut stdin = std::io::stdin().lock();
+        if feed == Feed::Cases {
+            let mut raw = Vec::new();
+            stdin.read_to_end(&mut raw)?;
+            return archive_cases(cfg, &mut store, today, &raw);
+        }
+        let path = archive_path(cfg, today, feed);
+        prepare(&path)?;
+        let mut file = std::fs::File::create(&path).with_context(|| format!("writing {}", path.display()))?;
+        std::io::copy(&mut stdin, &mut file).with_context(|| format!("writing {}", path.display()))?;
+        println!("Wrote {}", path.display());
+        return load(cfg, &mut store, today, feed);
+    }
+    let dir = opt.dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let downloads: Vec<(Feed, PathBuf)> = Feed::ALL
+        .into_iter()
+        .filter_map(|feed| {
+            let path = dir.join(feed.download_name());
+            if path.is_file() {
+                Some((feed, path))
+            } else {
+                println!("No {} in {}, skipping", feed.download_name(), dir.display());
+                None
+            }
+        })
+        .collect();
+    if downloads.is_empty() {
+        bail!("no raw downloads found in {}", dir.display());
+    }
+    // Only now, so a run with nothing to ingest leaves no empty store behind
+    let mut store = Store::open(cfg)?;
+    for (feed, path) in downloads {
+        if feed == Feed::Cases {
+            let raw = std::fs::read(&path).with_context(|| format!("reading {}", path.display()))?;
+            archive_cases(cfg, &mut store, today, &raw)?;
+        } else {
+            // The rest need no cleanup, and the test records are too big to hold
+            let dest = archive_path(cfg, today, feed);
+            prepare(&dest)?;
+            std::fs::copy(&path, &dest).with_context(|| format!("copying {} to {}", path.display(), dest.display()))?;
+            println!("Wrote {}", dest.display());
+            load(cfg, &mut store, today, feed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Where a feed is archived; for cases, the cleaned-up file.
+fn archive_path(cfg: &Config, today: &chrono::DateTime<chrono::Local>, feed: Feed) -> PathBuf {
+    match feed {
+        Feed::Cases => cases_file(cfg, today),
+        Feed::Hospitals => hosps_file(cfg, today),
+        Feed::PcrTests => pcr_tests_file(cfg, today),
+        Feed::Tests => tests_file(cfg, today),
+        Feed::VaxDoses => vax_doses_file(cfg, today),
+        Feed::VaxCoverage => vax_coverage_file(cfg, today),
+    }
+}
+
+/// Keep the raw cases feed, and write out the cleaned-up one and load it
+/// into the store.
+fn archive_cases(cfg: &Config, store: &mut Store, today: &chrono::DateTime<chrono::Local>, raw: &[u8]) -> Result<()> {
+    write_file(&cases_raw_file(cfg, today), raw)?;
+    let path = cases_file(cfg, today);
+    write_file(&path, &fix_cases(cfg, raw)?)?;
+    let recs = csvrecs::<CasesRecord>(cfg, &path)?;
+    store.put_cases(vintage_date(today), &recs)?;
+    print_case_totals(cfg, &recs);
+    Ok(())
+}
+
+/// Load an archived pass-through feed into the store, for the feeds it keeps.
+fn load(cfg: &Config, store: &mut Store, today: &chrono::DateTime<chrono::Local>, feed: Feed) -> Result<()> {
+    if feed == Feed::Hospitals {
+        let recs = csvrecs::<HospitalRecord>(cfg, hosps_file(cfg, today))?;
+        store.put_hospitals(vintage_date(today), &recs)?;
+    }
+    Ok(())
+}
+
+fn prepare(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    prepare(path)?;
+    std::fs::write(path, contents).with_context(|| format!("writing {}", path.display()))?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
+
+/// Dates show up as m/d/Y, Y-m-d, or Socrata's floating timestamps depending
+/// on which export path was used.
+fn parse_any_date(s: &str) -> Option<chrono::NaiveDate> {
+    let s = s.trim();
+    let s = s.split('T').next().unwrap_or(s);
+    let s = s.split(' ').next().unwrap_or(s);
+    ["%m/%d/%Y", "%Y-%m-%d", "%Y/%m/%d"]
+        .iter()
+        .find_map(|fmt| chrono::NaiveDate::parse_from_str(s, fmt).ok())
+}
+
+/// Clean up the raw cases feed the way fix_cases.py did: drop rows with no
+/// jurisdiction or an unparseable date, normalize dates, strip thousands
+/// separators from the counts, keep only the last row for a repeated
+/// (jurisdiction, date), and sort by jurisdiction then date.  Aliased
+/// headers are written back out under their expected names.
+fn fix_cases(cfg: &Config, raw: &[u8]) -> Result<Vec<u8>> {
+    let mut rdr = csv::Reader::from_reader(raw);
+    let headers = canonical_headers::<CasesRecord>(cfg, rdr.headers()?);
+    let col = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| anyhow!("cases feed has no {name:?} column"))
+    };
+    let jcol = col("Jurisdiction")?;
+    let dcol = col("Date")?;
+
+    let mut rows: std::collections::BTreeMap<(String, chrono::NaiveDate), Vec<String>> =
+        std::collections::BTreeMap::new();
+    for rec in rdr.records() {
+        let rec = rec?;
+        let jurisdiction = rec.get(jcol).unwrap_or("").trim();
+        let date = match rec.get(dcol).and_then(parse_any_date) {
+            Some(d) => d,
+            None => continue,
+        };
+        if jurisdiction.is_empty() {
+            continue;
+        }
+        let fields: Vec<String> = rec
+            .iter()
+            .enumerate()
+            .map(|(i, f)| match i {
+                i if i == jcol => jurisdiction.to_string(),
+                i if i == dcol => date.format("%m/%d/%Y").to_string(),
+                _ => clean_number(f),
+            })
+            .collect();
+        rows.insert((jurisdiction.to_string(), date), fields);
+    }
+
+    let mut wtr = csv::Writer::from_writer(Vec::new());
+    wtr.write_record(headers.iter().map(str::trim))?;
+    for fields in rows.values() {
+        wtr.write_record(fields)?;
+    }
+    Ok(wtr.into_inner()?)
+}
+
+/// "1,234" -> "1234"; anything that isn't a number is passed through trimmed.
+fn clean_number(field: &str) -> String {
+    let f = field.trim();
+    let stripped: String = f.chars().filter(|&c| c != ',').collect();
+    if !stripped.is_empty() && stripped.parse::<f64>().is_ok() {
+        stripped
+    } else {
+        f.to_string()
+    }
+}
+
+fn print_case_totals(cfg: &Config, recs: &[CasesRecord]) {
+    for j in &cfg.jurisdictions {
+        let total: u32 = recs
+            .iter()
+            .filter(|r| r.county == j.name)
+            .map(|r| r.new_cases.unwrap_or(0))
+            .sum();
+        println!("Cases total {}: {}", j.name, total);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(raw: &str) -> String {
+        String::from_utf8(fix_cases(&Config::default(), raw.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn strips_thousands_separators() {
+        let out = fixed("Jurisdiction,Date,New Cases\nPhiladelphia,01/02/2021,\"1,234\"\n");
+        assert_eq!(out, "Jurisdiction,Date,New Cases\nPhiladelphia,01/02/2021,1234\n");
+    }
+
+    #[test]
+    fn leaves_non_numbers_alone() {
+        assert_eq!(clean_number(" 12,345 "), "12345");
+        assert_eq!(clean_number("n/a"), "n/a");
+        assert_eq!(clean_number(""), "");
+    }
+
+    #[test]
+    fn last_duplicate_wins() {
+        let out = fixed(
+            "Jurisdiction,Date,New Cases\n\
+             Allegheny,01/02/2021,5\n\
+             Allegheny,2021-01-02,7\n\
+             Allegheny,01/03/2021,9\n",
+        );
+        assert_eq!(out, "Jurisdiction,Date,New Cases\nAllegheny,01/02/2021,7\nAllegheny,01/03/2021,9\n");
+    }
+
+    #[test]
+    fn normalizes_dates_and_sorts() {
+        let out = fixed(
+            "Jurisdiction,Date,New Cases\n\
+             Beaver,2021-01-03T00:00:00.000,3\n\
+             Allegheny,2021/01/02,2\n\
+             Allegheny,01/01/2021 12:00:00 AM,1\n",
+        );
+        assert_eq!(
+            out,
+            "Jurisdiction,Date,New Cases\n\
+             Allegheny,01/01/2021,1\n\
+             Allegheny,01/02/2021,2\n\
+             Beaver,01/03/2021,3\n"
+        );
+    }
+
+    #[test]
+    fn drops_rows_without_jurisdiction_or_date() {
+        let out = fixed("Jurisdiction,Date,New Cases\n,01/02/2021,4\nAllegheny,someday,5\n");
+        assert_eq!(out, "Jurisdiction,Date,New Cases\n");
+    }
+
+    #[test]
+    fn parses_each_date_format() {
+        let d = chrono::NaiveDate::from_ymd(2021, 3, 4);
+        for s in ["03/04/2021", "2021-03-04", "2021/03/04", "2021-03-04T00:00:00.000", " 3/4/2021 "] {
+            assert_eq!(parse_any_date(s), Some(d), "{s}");
+        }
+        assert_eq!(parse_any_date("04-03-2021"), None);
+    }
+}
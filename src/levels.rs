@@ -0,0 +1,338 @@
+//! Risk levels, defined as data.
+//!
+//! CDC has redefined its levels more than once: the 2021 community
+//! transmission bands went by cases per 100k alone, while the February 2022
+//! Community Levels go by hospital load with thresholds that depend on cases.
+//! Each definition is a named rule set with the dates it was in force, so a
+//! report for a past date uses the levels of that date.  Rule sets in the
+//! config replace the built-in one of the same name, or add to them.
+//!
+//! The PA hospital feed has no admissions column, so rules on
+//! `admissions_week_100k` never fire on our data; they're kept so the CDC
+//! definition reads as published.
+
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::population::per_100k;
+use crate::HospitalRecord;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// New cases per 100k in the last week
+    #[serde(rename = "cases_week_100k")]
+    CasesWeek100k,
+    /// The higher of the last two 7 day windows' cases per 100k, each from a
+    /// whole-number daily average, as the transmission risk line always used
+    #[serde(rename = "cases_week_100k_high")]
+    CasesWeek100kHigh,
+    /// New COVID admissions per 100k in the last week
+    #[serde(rename = "admissions_week_100k")]
+    AdmissionsWeek100k,
+    /// Percent of staffed inpatient beds used by COVID patients, 7 day average
+    BedsPct,
+}
+
+impl Metric {
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::CasesWeek100k => "cases per 100k last week",
+            Metric::CasesWeek100kHigh => "cases per 100k, higher of the last two weeks",
+            Metric::AdmissionsWeek100k => "new COVID admissions per 100k last week",
+            Metric::BedsPct => "COVID share of staffed inpatient beds",
+        }
+    }
+
+    fn unit(self) -> &'static str {
+        match self {
+            Metric::BedsPct => "%",
+            _ => "",
+        }
+    }
+}
+
+/// The inputs to the levels, as of the last complete week.
+#[derive(Debug, Clone, Copy)]
+pub struct Indicators {
+    pub cases_week_100k: f64,
+    pub cases_week_100k_high: f64,
+    pub admissions_week_100k: Option<f64>,
+    pub beds_pct: Option<f64>,
+}
+
+impl Indicators {
+    /// Indicators for the week before the last record, which is usually
+    /// incomplete.  None without a population or a full week of records.
+    pub fn from_records(recs: &[HospitalRecord], population: Option<u32>) -> Option<Indicators> {
+        let pop = population?;
+        let last = recs.len().checked_sub(1)?;
+        let week = recs.get(last.checked_sub(7)?..last)?;
+        let cases: u32 = week.iter().map(|r| r.new_cases.unwrap_or(0)).sum();
+        // The same window and the one a day earlier, from truncated averages
+        let high = [0, 1]
+            .iter()
+            .filter_map(|step| recs.get(last.checked_sub(7 + step)?..last - step))
+            .map(|w| (w.iter().map(|r| r.new_cases.unwrap_or(0)).sum::<u32>() as f64 / 7.0).floor() * 7.0)
+            .fold(0.0, f64::max);
+        let shares: Vec<f64> = week
+            .iter()
+            .filter_map(|r| {
+                let beds = r.med_surg_total? + r.adult_icu_beds_total?;
+                let covid = r.covid_hospitalized?;
+                (beds > 0).then(|| covid as f64 * 100.0 / beds as f64)
+            })
+            .collect();
+        Some(Indicators {
+            cases_week_100k: per_100k(cases as f64, pop),
+            cases_week_100k_high: per_100k(high, pop),
+            admissions_week_100k: None,
+            beds_pct: (!shares.is_empty()).then(|| shares.iter().sum::<f64>() / shares.len() as f64),
+        })
+    }
+
+    pub fn get(&self, metric: Metric) -> Option<f64> {
+        match metric {
+            Metric::CasesWeek100k => Some(self.cases_week_100k),
+            Metric::CasesWeek100kHigh => Some(self.cases_week_100k_high),
+            Metric::AdmissionsWeek100k => self.admissions_week_100k,
+            Metric::BedsPct => self.beds_pct,
+        }
+    }
+}
+
+/// One indicator's banding.  It only applies while weekly cases per 100k
+/// are within `cases_at_least..cases_below`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub metric: Metric,
+    /// Lowest value of each level after the first
+    pub thresholds: Vec<f64>,
+    pub cases_at_least: Option<f64>,
+    pub cases_below: Option<f64>,
+}
+
+impl Rule {
+    fn applies(&self, ind: &Indicators) -> bool {
+        self.cases_at_least.is_none_or(|c| ind.cases_week_100k >= c)
+            && self.cases_below.is_none_or(|c| ind.cases_week_100k < c)
+    }
+
+    /// Index into the rule set's levels.
+    fn grade(&self, value: f64) -> usize {
+        self.thresholds.iter().filter(|t| value >= **t).count()
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet {
+    pub name: String,
+    /// Label for the report line, e.g. "CDC Community level"
+    pub title: String,
+    /// First and last dates the rule set was in force; open-ended if unset
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    /// Level names, lowest first
+    pub levels: Vec<String>,
+    pub rules: Vec<Rule>,
+}
+
+/// A rule set's verdict and the indicator that set it.
+#[derive(Debug, Clone)]
+pub struct Assessment<'a> {
+    pub rule_set: &'a RuleSet,
+    pub level: &'a str,
+    pub rule: &'a Rule,
+    pub value: f64,
+    pub cases_week_100k: f64,
+}
+
+impl RuleSet {
+    fn in_force(&self, date: chrono::NaiveDate) -> bool {
+        self.from.is_none_or(|f| date >= f) && self.to.is_none_or(|t| date <= t)
+    }
+
+    /// The highest level any applicable rule puts `ind` at.  Ties go to the
+    /// rule listed first.  None if no rule has a value to go on.
+    pub fn assess(&self, ind: &Indicators) -> Option<Assessment<'_>> {
+        let (idx, rule, value) = self
+            .rules
+            .iter()
+            .filter(|r| r.applies(ind))
+            .filter_map(|r| ind.get(r.metric).map(|v| (r.grade(v), r, v)))
+            .rev()
+            .max_by_key(|(idx, _, _)| *idx)?;
+        Some(Assessment {
+            rule_set: self,
+            level: self.levels.get(idx).or_else(|| self.levels.last())?,
+            rule,
+            value,
+            cases_week_100k: ind.cases_week_100k,
+        })
+    }
+}
+
+impl std::fmt::Display for Assessment<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} (set by {}, {:.1}{}",
+            self.rule_set.title,
+            self.level,
+            self.rule.metric.name(),
+            self.value,
+            self.rule.metric.unit()
+        )?;
+        if !matches!(self.rule.metric, Metric::CasesWeek100k | Metric::CasesWeek100kHigh)
+            && (self.rule.cases_at_least.is_some() || self.rule.cases_below.is_some())
+        {
+            write!(f, "; {:.0} cases/100k last week", self.cases_week_100k)?;
+        }
+        write!(f, ")")
+    }
+}
+
+fn rule(metric: Metric, thresholds: &[f64], cases_at_least: Option<f64>, cases_below: Option<f64>) -> Rule {
+    Rule {
+        metric,
+        thresholds: thresholds.to_vec(),
+        cases_at_least,
+        cases_below,
+    }
+}
+
+/// CDC community transmission (2021) and Community Levels (February 2022).
+fn builtin() -> Vec<RuleSet> {
+    let levels = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+    vec![
+        RuleSet {
+            name: "cdc-transmission-2021".to_string(),
+            title: "Transmission risk".to_string(),
+            from: None,
+            to: Some(chrono::NaiveDate::from_ymd(2022, 2, 24)),
+            levels: levels(&["Low", "Moderate", "Substantial", "High"]),
+            rules: vec![rule(Metric::CasesWeek100kHigh, &[10.0, 50.0, 100.0], None, None)],
+        },
+        RuleSet {
+            name: "cdc-community-2022".to_string(),
+            title: "CDC Community level".to_string(),
+            from: Some(chrono::NaiveDate::from_ymd(2022, 2, 25)),
+            to: None,
+            levels: levels(&["Low", "Medium", "High"]),
+            rules: vec![
+                rule(Metric::AdmissionsWeek100k, &[10.0, 20.0], None, Some(200.0)),
+                rule(Metric::BedsPct, &[10.0, 15.0], None, Some(200.0)),
+                rule(Metric::AdmissionsWeek100k, &[0.0, 10.0], Some(200.0), None),
+                rule(Metric::BedsPct, &[0.0, 10.0], Some(200.0), None),
+            ],
+        },
+    ]
+}
+
+/// The built-in rule sets with the configured ones laid over them.
+pub fn rule_sets(cfg: &Config) -> Vec<RuleSet> {
+    let mut sets = builtin();
+    for custom in &cfg.level_rules {
+        sets.retain(|s| s.name != custom.name);
+        sets.push(custom.clone());
+    }
+    sets
+}
+
+/// The rule set in force on `date`; where ranges overlap, the one listed
+/// last wins, so a custom rule set can take over from a built-in one.
+pub fn in_force(sets: &[RuleSet], date: chrono::NaiveDate) -> Option<&RuleSet> {
+    sets.iter().rev().find(|s| s.in_force(date))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ind(cases: f64, beds_pct: Option<f64>) -> Indicators {
+        Indicators {
+            cases_week_100k: cases,
+            cases_week_100k_high: cases,
+            admissions_week_100k: None,
+            beds_pct,
+        }
+    }
+
+    fn set(name: &str) -> RuleSet {
+        builtin().into_iter().find(|s| s.name == name).unwrap()
+    }
+
+    fn level(set: &RuleSet, ind: Indicators) -> Option<String> {
+        set.assess(&ind).map(|a| a.level.to_string())
+    }
+
+    #[test]
+    fn transmission_levels_at_each_threshold() {
+        let set = set("cdc-transmission-2021");
+        for (cases, expected) in [
+            (0.0, "Low"),
+            (9.9, "Low"),
+            (10.0, "Moderate"),
+            (49.9, "Moderate"),
+            (50.0, "Substantial"),
+            (99.9, "Substantial"),
+            (100.0, "High"),
+            (1000.0, "High"),
+        ] {
+            assert_eq!(level(&set, ind(cases, None)).as_deref(), Some(expected), "{cases} cases");
+        }
+    }
+
+    #[test]
+    fn community_levels_below_200_cases() {
+        let set = set("cdc-community-2022");
+        for (beds, expected) in [(0.0, "Low"), (9.9, "Low"), (10.0, "Medium"), (14.9, "Medium"), (15.0, "High")] {
+            assert_eq!(level(&set, ind(199.9, Some(beds))).as_deref(), Some(expected), "{beds}% beds");
+        }
+    }
+
+    #[test]
+    fn community_levels_from_200_cases() {
+        let set = set("cdc-community-2022");
+        for (beds, expected) in [(0.0, "Medium"), (9.9, "Medium"), (10.0, "High"), (15.0, "High")] {
+            assert_eq!(level(&set, ind(200.0, Some(beds))).as_deref(), Some(expected), "{beds}% beds");
+        }
+        let a = set.assess(&ind(250.0, Some(12.0))).unwrap();
+        assert_eq!(a.rule.metric, Metric::BedsPct);
+        assert_eq!(a.value, 12.0);
+    }
+
+    #[test]
+    fn community_level_needs_hospital_data() {
+        assert_eq!(level(&set("cdc-community-2022"), ind(500.0, None)), None);
+    }
+
+    #[test]
+    fn in_force_switches_on_2022_02_25() {
+        let sets = builtin();
+        let on = |y, m, d| in_force(&sets, chrono::NaiveDate::from_ymd(y, m, d)).map(|s| s.name.as_str());
+        assert_eq!(on(2021, 6, 1), Some("cdc-transmission-2021"));
+        assert_eq!(on(2022, 2, 24), Some("cdc-transmission-2021"));
+        assert_eq!(on(2022, 2, 25), Some("cdc-community-2022"));
+        assert_eq!(on(2023, 1, 1), Some("cdc-community-2022"));
+    }
+
+    #[test]
+    fn configured_rule_sets_replace_or_override() {
+        let mut cfg = Config::default();
+        let mut custom = set("cdc-transmission-2021");
+        custom.to = None;
+        custom.from = Some(chrono::NaiveDate::from_ymd(2022, 6, 1));
+        custom.title = "Local".to_string();
+        cfg.level_rules.push(custom);
+        let sets = rule_sets(&cfg);
+        assert_eq!(sets.len(), 2);
+        let on = |y, m, d| in_force(&sets, chrono::NaiveDate::from_ymd(y, m, d)).map(|s| s.title.as_str());
+        // The replaced set no longer covers 2021, and wins over the built-in from June
+        assert_eq!(on(2021, 6, 1), None);
+        assert_eq!(on(2022, 3, 1), Some("CDC Community level"));
+        assert_eq!(on(2022, 6, 1), Some("Local"));
+    }
+}
@@ -1,18 +1,38 @@
 use anyhow::Result;
-use chrono::{Datelike, TimeZone};
+use chrono::TimeZone;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
 use plotters::prelude::*;
 
-use std::{collections::HashMap, iter::Iterator};
-
-const CSVDIR: &str = "/home/dga/pa_data";
-const CASES_PREFIX: &str = "daily";
-const HOSP_PREFIX: &str = "today";
-const PCR_PREFIX: &str = "pcr_test_counts";
-const TESTS_DIR: &str = "/home/dga/testday";
+use std::{iter::Iterator, path::Path, path::PathBuf};
+
+mod breakdown;
+mod config;
+mod epicurve;
+mod gaps;
+mod growth;
+mod icu;
+mod ingest;
+mod levels;
+mod nowcast;
+mod parse;
+mod population;
+mod positivity;
+mod ratio;
+mod reportlag;
+mod revisions;
+mod rt;
+mod schema;
+mod severity;
+mod store;
+mod table;
+mod testrecs;
+mod weekday;
+use config::Config;
+use parse::csvrecs;
+use schema::Schema;
 
 /// Importer for [OpendataPA hospitalization data](https://data.pa.gov/Covid-19/COVID-19-Aggregate-Hospitalizations-Current-Daily-/kayn-sjhx)
 ///
@@ -43,29 +63,78 @@ pub struct HospitalRecord {
     new_cases: Option<u32>,
 }
 
+impl Schema for HospitalRecord {
+    const KIND: &'static str = "hospitals";
+    const COLUMNS: &'static [&'static str] = &[
+        "County",
+        "Date of data",
+        "Adult ICU Beds Available",
+        "Adult ICU Beds Total",
+        "Medical/Surgical Beds Available",
+        "Medical/Surgical Beds Total",
+        "COVID-19 Patients Hospitalized",
+        "COVID-19 Patients on Ventilators",
+        "COVID-ICU",
+    ];
+    const DATES: &'static [(&'static str, &'static str)] = &[("Date of data", mdY_date_format::FORMAT)];
+}
+
 /// Importer for [WPRDC test results data](https://data.wprdc.org/dataset/allegheny-county-covid-19-tests-cases-and-deaths)
 ///
 /// Requires the [raw data in CSV format](https://data.wprdc.org/dataset/allegheny-county-covid-19-tests-cases-and-deaths/resource/4051a85a-bf92-45fc-adc6-b31eb8efaad4) (warning, this is a 60+MB download)
+///
+/// The file is too big to hold as owned rows, so records borrow from the CSV
+/// row and are streamed through `testrecs::fold_test_records`.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct TestRecord {
-    indv_id: String,
+pub struct TestRecord<'a> {
+    indv_id: &'a str,
     #[serde(with = "Ymd_dash_date_format")]
     collection_date: chrono::NaiveDate,
     #[serde(with = "Ymd_dash_date_format")]
     report_date: chrono::NaiveDate,
-    test_result: String,
-    case_status: String,
-    hospital_flag: String,
-    icu_flag: String,
-    vent_flag: String,
-    age_bucket: String,
-    sex: String,
-    race: String,
-    ethnicity: String,
+    test_result: &'a str,
+    case_status: &'a str,
+    hospital_flag: &'a str,
+    icu_flag: &'a str,
+    vent_flag: &'a str,
+    age_bucket: &'a str,
+    sex: &'a str,
+    race: &'a str,
+    ethnicity: &'a str,
     #[serde(with = "Ymd_dash_date_format")]
     update_date: chrono::NaiveDate,
 }
 
+impl TestRecord<'_> {
+    fn is_case(&self) -> bool {
+        self.case_status == "Probable" || self.case_status == "Confirmed"
+    }
+}
+
+impl Schema for TestRecord<'_> {
+    const KIND: &'static str = "tests";
+    const COLUMNS: &'static [&'static str] = &[
+        "indv_id",
+        "collection_date",
+        "report_date",
+        "test_result",
+        "case_status",
+        "hospital_flag",
+        "icu_flag",
+        "vent_flag",
+        "age_bucket",
+        "sex",
+        "race",
+        "ethnicity",
+        "update_date",
+    ];
+    const DATES: &'static [(&'static str, &'static str)] = &[
+        ("collection_date", Ymd_dash_date_format::FORMAT),
+        ("report_date", Ymd_dash_date_format::FORMAT),
+        ("update_date", Ymd_dash_date_format::FORMAT),
+    ];
+}
+
 /// Importer for
 /// [OpendataPA cases data](https://data.pa.gov/Covid-19/COVID-19-Aggregate-Cases-Current-Daily-County-Heal/j72v-r42c)
 ///
@@ -81,11 +150,80 @@ pub struct CasesRecord {
     new_cases: Option<u32>,
 }
 
+impl Schema for CasesRecord {
+    const KIND: &'static str = "cases";
+    const COLUMNS: &'static [&'static str] = &["Jurisdiction", "Date", "New Cases"];
+    const DATES: &'static [(&'static str, &'static str)] = &[("Date", mdY_date_format::FORMAT)];
+}
+
+/// Importer for
+/// [OpendataPA vaccinations by day](https://data.pa.gov/Covid-19/COVID-19-Vaccinations-by-Day-by-County-of-Residence/bicw-3gwi)
+///
+/// Requires the [raw data feed in CSV format](https://data.pa.gov/api/views/bicw-3gwi/rows.csv?accessType=DOWNLOAD)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaxDayRecord {
+    #[serde(rename = "Date")]
+    #[serde(with = "mdY_date_format")]
+    date: chrono::NaiveDate,
+    #[serde(rename = "County")]
+    county: String,
+    #[serde(rename = "Partially Covered")]
+    partially_covered: Option<u32>,
+    #[serde(rename = "Fully Covered")]
+    fully_covered: Option<u32>,
+    #[serde(rename = "First Booster Dose")]
+    first_booster: Option<u32>,
+    #[serde(rename = "Second Booster Dose")]
+    second_booster: Option<u32>,
+}
+
+impl Schema for VaxDayRecord {
+    const KIND: &'static str = "vax-doses";
+    const COLUMNS: &'static [&'static str] = &[
+        "Date",
+        "County",
+        "Partially Covered",
+        "Fully Covered",
+        "First Booster Dose",
+        "Second Booster Dose",
+    ];
+    const DATES: &'static [(&'static str, &'static str)] = &[("Date", mdY_date_format::FORMAT)];
+}
+
+/// Importer for
+/// [OpendataPA cumulative vaccinations by county](https://data.pa.gov/Covid-19/COVID-19-Vaccinations-by-Residence-Current-County-H/niuh-2xe3)
+///
+/// Requires the [raw data feed in CSV format](https://data.pa.gov/api/views/niuh-2xe3/rows.csv?accessType=DOWNLOAD)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VaxCoverageRecord {
+    #[serde(rename = "County")]
+    county: String,
+    #[serde(rename = "Partially Covered")]
+    partially_covered: Option<u32>,
+    #[serde(rename = "Fully Covered")]
+    fully_covered: Option<u32>,
+    #[serde(rename = "First Booster Dose")]
+    first_booster: Option<u32>,
+    #[serde(rename = "Second Booster Dose")]
+    second_booster: Option<u32>,
+}
+
+impl Schema for VaxCoverageRecord {
+    const KIND: &'static str = "vax-coverage";
+    const COLUMNS: &'static [&'static str] = &[
+        "County",
+        "Partially Covered",
+        "Fully Covered",
+        "First Booster Dose",
+        "Second Booster Dose",
+    ];
+}
+
 #[allow(non_snake_case)]
 mod mdY_date_format {
     use serde::{self, Deserialize, Deserializer, Serializer};
 
-    const FORMAT: &str = "%m/%d/%Y";
+    pub const FORMAT: &str = "%m/%d/%Y";
     pub fn serialize<S>(nd: &chrono::NaiveDate, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -99,7 +237,8 @@ mod mdY_date_format {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        chrono::NaiveDate::parse_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
+        chrono::NaiveDate::parse_from_str(&s, FORMAT)
+            .map_err(|e| serde::de::Error::custom(format!("{e} in date {s:?}")))
     }
 }
 
@@ -107,7 +246,7 @@ mod mdY_date_format {
 mod Ymd_dash_date_format {
     use serde::{self, Deserialize, Deserializer, Serializer};
 
-    const FORMAT: &str = "%Y-%m-%d";
+    pub const FORMAT: &str = "%Y-%m-%d";
     pub fn serialize<S>(nd: &chrono::NaiveDate, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -121,30 +260,53 @@ mod Ymd_dash_date_format {
         D: Deserializer<'de>,
     {
         let s = String::deserialize(deserializer)?;
-        chrono::NaiveDate::parse_from_str(&s, FORMAT).map_err(serde::de::Error::custom)
+        chrono::NaiveDate::parse_from_str(&s, FORMAT)
+            .map_err(|e| serde::de::Error::custom(format!("{e} in date {s:?}")))
     }
 }
 
-fn csvrecs<T>(filename: &str) -> Result<Vec<T>>
+/// Draw a polyline (in backend pixel coordinates) as a dashed line.  plotters
+/// has no dashed series, so the dashes are cut here and drawn onto `root`.
+fn draw_dashed<DB: DrawingBackend>(
+    root: &DrawingArea<DB, plotters::coord::Shift>,
+    points: &[(i32, i32)],
+    style: &ShapeStyle,
+) -> Result<()>
 where
-    T: serde::de::DeserializeOwned,
-    T: std::fmt::Debug,
+    DB::ErrorType: 'static,
 {
-    let infile = std::fs::File::open(filename)?;
-    let mut rdr = csv::Reader::from_reader(infile);
-    Ok(rdr
-        .deserialize()
-        .filter_map(Option::Some)
-        .filter_map(|o| {
-            // if o.is_err() {
-            //     println!("Error unpacking {:?}", o);
-            // }
-            o.ok()
-        })
-        .collect())
+    const DASH: f64 = 8.0;
+    const GAP: f64 = 5.0;
+    // Distance into the current dash (or gap) period
+    let mut phase = 0.0;
+    for seg in points.windows(2) {
+        let (x0, y0) = (seg[0].0 as f64, seg[0].1 as f64);
+        let (dx, dy) = (seg[1].0 as f64 - x0, seg[1].1 as f64 - y0);
+        let len = (dx * dx + dy * dy).sqrt();
+        let mut t = 0.0;
+        while t < len {
+            let in_dash = phase < DASH;
+            let step = if in_dash { DASH - phase } else { DASH + GAP - phase }.min(len - t);
+            if in_dash {
+                let at = |d: f64| ((x0 + dx * d / len) as i32, (y0 + dy * d / len) as i32);
+                root.draw(&PathElement::new(vec![at(t), at(t + step)], style.clone()))?;
+            }
+            t += step;
+            phase = (phase + step) % (DASH + GAP);
+        }
+    }
+    Ok(())
 }
 
-fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool, y_truncate: bool) -> Result<()> {
+fn plot_jurisdiction(
+    cfg: &Config,
+    recs: &[HospitalRecord],
+    jurisdiction: &str,
+    is_60d: bool,
+    y_truncate: bool,
+    nowcast: Option<&nowcast::Nowcast>,
+    weekday: Option<&weekday::WeekdayEffect>,
+) -> Result<()> {
     let mut img_path = std::path::PathBuf::from(str::replace(jurisdiction, " ", "_"));
     img_path.set_extension("png");
     let dates: Vec<chrono::NaiveDate> = recs.iter().map(|x| x.date).collect();
@@ -160,6 +322,11 @@ fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool,
         filled: true,
         stroke_width: 1,
     };
+    let adjusted_cases_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(4).mix(0.5).to_rgba(),
+        filled: true,
+        stroke_width: 1,
+    };
     let avg_cases_style = plotters::style::ShapeStyle {
             color: plotters::style::Palette99::pick(1).mix(0.9).to_rgba(),
             filled: true,
@@ -175,6 +342,16 @@ fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool,
         filled: true,
         stroke_width: 2,
     };
+    let nowcast_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(1).mix(0.9).to_rgba(),
+        filled: true,
+        stroke_width: 2,
+    };
+    let nowcast_band_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(1).mix(0.15).to_rgba(),
+        filled: true,
+        stroke_width: 0,
+    };
 
     let max_date = *(dates.iter().max().unwrap()) + chrono::Duration::days(1);
     let min_date: chrono::NaiveDate = if is_60d {
@@ -205,6 +382,26 @@ fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool,
     let dates7day = recs.iter().skip(6).map(|x| x.date).take(cases7day.len());
     let datecases7day = dates7day.zip(cases7day);
 
+    // Nowcasted 7 day average (estimate, lo, hi) for every window that
+    // touches a nowcasted date, plus the window before so the dashed line
+    // picks up where the solid one leaves off.
+    let mut nowcast7day: Vec<(chrono::NaiveDate, f64, f64, f64)> = Vec::new();
+    if let Some(nc) = nowcast {
+        let windows: Vec<&[HospitalRecord]> = recs[..recs.len() - 1].windows(7).collect();
+        if let Some(first) = windows
+            .iter()
+            .position(|w| w.iter().any(|r| nc.get(r.date).is_some()))
+        {
+            for w in &windows[first.saturating_sub(1)..] {
+                let (est, lo, hi) = w.iter().fold((0.0, 0.0, 0.0), |acc, r| {
+                    let (e, l, h) = nc.adjusted(r.date, r.new_cases.unwrap_or(0));
+                    (acc.0 + e / 7.0, acc.1 + l / 7.0, acc.2 + h / 7.0)
+                });
+                nowcast7day.push((w[6].date, est, lo, hi));
+            }
+        }
+    }
+
     let mut chart = ChartBuilder::on(&root)
         .margin(10)
         .caption(
@@ -215,7 +412,7 @@ fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool,
         .set_label_area_size(LabelAreaPosition::Right, 60)
         .set_label_area_size(LabelAreaPosition::Bottom, 40)
         .build_cartesian_2d(min_date..max_date, 0u32..max_y)?;
-    chart.configure_mesh().bold_line_style(&BLACK.mix(0.10)).light_line_style(&BLACK.mix(0.05)).x_labels(10).x_desc("Date").draw()?;
+    chart.configure_mesh().bold_line_style(BLACK.mix(0.10)).light_line_style(BLACK.mix(0.05)).x_labels(10).x_desc("Date").draw()?;
     chart
         .draw_series(LineSeries::new(
             recs.iter()
@@ -225,26 +422,71 @@ fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool,
         ))?
         .label("Daily new cases")
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], daily_cases_style.clone()));
+    if let Some(w) = weekday {
+        chart
+            .draw_series(LineSeries::new(
+                w.adjusted_series(&recs[..recs.len() - 1])
+                    .into_iter()
+                    .map(|(d, v)| (d, (v.round() as u32).min(max_y))),
+                adjusted_cases_style.clone(),
+            ))?
+            .label("Weekday-adjusted daily cases")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], adjusted_cases_style.clone()));
+    }
     chart
         .draw_series(LineSeries::new(datecases7day, avg_cases_style.clone()))?
         .label("7 day avg new cases")
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], avg_cases_style.clone()));
-    let hosp_cases = cleanup(recs.iter().map(|x| x.covid_hospitalized));
-    chart
-        .draw_series(LineSeries::new(
-            recs.iter().map(|x| x.date).zip(hosp_cases.into_iter()),
-            hosp_style.clone(),
-        ))?
-        .label("Total hospitalized")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], hosp_style.clone()));
-    let icu_cases = cleanup(recs.iter().map(|x| x.covid_icu));
-    chart
-        .draw_series(LineSeries::new(
-            recs.iter().map(|x| x.date).zip(icu_cases.into_iter()),
-            icu_style.clone(),
-        ))?
-        .label("ICU beds used")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], icu_style.clone()));
+    if !nowcast7day.is_empty() {
+        let clamp = |v: f64| (v.round() as u32).min(max_y);
+        let band: Vec<(chrono::NaiveDate, u32)> = nowcast7day
+            .iter()
+            .map(|(d, _, _, hi)| (*d, clamp(*hi)))
+            .chain(nowcast7day.iter().rev().map(|(d, _, lo, _)| (*d, clamp(*lo))))
+            .collect();
+        chart.draw_series(std::iter::once(Polygon::new(band, nowcast_band_style)))?;
+        let pixels: Vec<(i32, i32)> = nowcast7day
+            .iter()
+            .map(|(d, est, _, _)| chart.backend_coord(&(*d, clamp(*est))))
+            .collect();
+        draw_dashed(&root, &pixels, &nowcast_style)?;
+        chart
+            .draw_series(std::iter::empty::<PathElement<(chrono::NaiveDate, u32)>>())?
+            .label("Nowcast 7 day avg (80% range)")
+            .legend(|(x, y)| {
+                EmptyElement::at((x, y))
+                    + PathElement::new(vec![(0, 0), (8, 0)], nowcast_style.clone())
+                    + PathElement::new(vec![(13, 0), (20, 0)], nowcast_style.clone())
+            });
+    }
+    let dates: Vec<chrono::NaiveDate> = recs.iter().map(|x| x.date).collect();
+    let mut any_imputed = false;
+    for (label, vals, style) in [
+        ("Total hospitalized", gaps::fill(cfg.gap_policy, recs.iter().map(|x| x.covid_hospitalized)), &hosp_style),
+        ("ICU beds used", gaps::fill(cfg.gap_policy, recs.iter().map(|x| x.covid_icu)), &icu_style),
+    ] {
+        for (i, run) in gaps::runs(&dates, &vals).into_iter().enumerate() {
+            let series = chart.draw_series(LineSeries::new(run, style.clone()))?;
+            if i == 0 {
+                series
+                    .label(label)
+                    .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
+            }
+        }
+        // Imputed points get hollow markers so they can't pass for data
+        let hollow = ShapeStyle { filled: false, stroke_width: 1, ..style.clone() };
+        chart.draw_series(dates.iter().zip(&vals).filter(|(_, p)| p.imputed).map(|(d, p)| {
+            any_imputed = true;
+            Circle::new((*d, p.value.unwrap()), 4, hollow.clone())
+        }))?;
+    }
+    if any_imputed {
+        let hollow = ShapeStyle { filled: false, stroke_width: 1, ..BLACK.into() };
+        chart
+            .draw_series(std::iter::empty::<Circle<(chrono::NaiveDate, u32), i32>>())?
+            .label(format!("Filled-in value ({})", cfg.gap_policy.name()))
+            .legend(move |(x, y)| Circle::new((x + 10, y), 4, hollow.clone()));
+    }
 
     chart
         .draw_series(LineSeries::new(
@@ -255,106 +497,240 @@ fn plot_jurisdiction(recs: &[HospitalRecord], jurisdiction: &str, is_60d: bool,
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], cur_cases_style.to_owned()));
     chart
         .configure_series_labels()
-        .border_style(&BLACK)
+        .border_style(BLACK)
         .position(SeriesLabelPosition::UpperLeft)
         .draw()?;
     Ok(())
 }
 
-fn printstats(recs: &[HospitalRecord], icunorm: u32, icunormfree: u32, population: Option<u32>) {
+/// Latest value of a filled series, its change from the previous value, and
+/// whether it was filled in.  None if the latest value is missing.
+fn latest_change(points: &[gaps::Point]) -> Option<(u32, i32, bool)> {
+    let (last, rest) = points.split_last()?;
+    let newest = last.value?;
+    let prev = rest.iter().rev().find_map(|p| p.value).unwrap_or(newest);
+    Some((newest, newest as i32 - prev as i32, last.imputed))
+}
+
+fn printstats(
+    cfg: &Config,
+    recs: &[HospitalRecord],
+    icu_baseline: Option<icu::Baseline>,
+    population: Option<u32>,
+    nowcast: Option<&nowcast::Nowcast>,
+    weekday: Option<&weekday::WeekdayEffect>,
+) {
     let last = recs.len() - 1;
-    let newh = recs[last].covid_hospitalized.unwrap();
-    let prev = recs[last - 1]
-        .covid_hospitalized
-        .unwrap_or_else(|| recs[last - 2].covid_hospitalized.unwrap()) as i32;
-    let hd = newh as i32 - prev as i32;
-    //let hd = newh as i32 - recs[last - 1].covid_hospitalized.unwrap() as i32;
-    println!("Hospitalizations are {:+} to {newh}  ", hd);
-    let newi = recs[last].covid_icu.unwrap();
-    let previ = recs[last - 1]
-        .covid_icu
-        .unwrap_or_else(|| recs[last - 2].covid_icu.unwrap()) as i32;
-    let id = newi as i32 - previ;
-    // recs[last - 1].covid_icu.unwrap() as i32;
-    print!("ICUs are {:+} to {} ", id, newi);
-    println!(
-        "({:.0}% full)  ",
-        ((((icunorm - icunormfree + newi) * 100) as f32) / (icunorm as f32))
-    );
-
-    let highest_cases = [0,1].iter().map(|step| {
+    let filled = |imputed: bool| if imputed { " (filled in)" } else { "" };
+    let hosp = latest_change(&gaps::fill(cfg.gap_policy, recs.iter().map(|x| x.covid_hospitalized)));
+    match hosp {
+        Some((newh, hd, imputed)) => println!("Hospitalizations are {:+} to {newh}{}  ", hd, filled(imputed)),
+        None => println!("Hospitalizations not reported for {}  ", recs[last].date),
+    }
+    let icu = latest_change(&gaps::fill(cfg.gap_policy, recs.iter().map(|x| x.covid_icu)));
+    match icu {
+        Some((newi, id, imputed)) => {
+            print!("ICUs are {:+} to {}{} ", id, newi, filled(imputed));
+            let normalized = icu_baseline.map(|b| format!("{:.0}% full vs. baseline", b.normalized_full(newi)));
+            let actual = icu::actual_full(&recs[last]).map(|f| format!("{:.0}% of staffed beds in use", f));
+            let fullness: Vec<String> = normalized.into_iter().chain(actual).collect();
+            if fullness.is_empty() {
+                println!(" ");
+            } else {
+                println!("({})  ", fullness.join(", "));
+            }
+        }
+        None => println!("ICUs not reported for {}  ", recs[last].date),
+    }
+
+    for step in [0, 1] {
         let cases_7_day_avg = recs[last - 7 - step..last - step]
             .iter()
             .map(|x| x.new_cases.unwrap_or(0) as f32)
             .sum::<f32>()
             / 7.0;
-
         println!("Step{} 7 day avg to {:.0} cases/day  ", step, cases_7_day_avg);
-        cases_7_day_avg as u32
-    }).max().unwrap();
+    }
+    if let Some(w) = weekday {
+        let adjusted = w.adjusted_series(&recs[last - 7..last]);
+        let (date, latest) = adjusted[adjusted.len() - 1];
+        println!(
+            "Weekday-adjusted {} cases/day on {} (7 day avg {:.0})  ",
+            latest.round(),
+            date.format("%a %b %-d"),
+            adjusted.iter().map(|(_, v)| v).sum::<f64>() / 7.0
+        );
+    }
+
+    let window = cfg.growth.window;
+    // The recent days are undercounted; fit the nowcast instead where there is one
+    let cases: Vec<f64> = recs[..last]
+        .iter()
+        .map(|r| {
+            let observed = r.new_cases.unwrap_or(0);
+            nowcast.map_or(observed as f64, |nc| nc.adjusted(r.date, observed).0)
+        })
+        .collect();
+    let cases7day: Vec<f64> = cases.windows(7).map(|w| w.iter().sum::<f64>() / 7.0).collect();
+    let cases_label = if nowcast.is_some() { "Cases (7 day avg, nowcast)" } else { "Cases (7 day avg)" };
+    let census = |vals: Vec<gaps::Point>| -> Vec<f64> {
+        vals.iter().map(|p| p.value.map_or(0.0, |v| v as f64)).collect()
+    };
+    for (what, series) in [
+        (cases_label, cases7day),
+        ("Hospitalizations", census(gaps::fill(cfg.gap_policy, recs.iter().map(|x| x.covid_hospitalized)))),
+        ("ICU", census(gaps::fill(cfg.gap_policy, recs.iter().map(|x| x.covid_icu)))),
+    ] {
+        if let Some(g) = growth::Growth::fit(&series, window) {
+            println!("{what} over the last {window} days: {g}  ");
+        }
+    }
+
+    if let Some(nc) = nowcast {
+        let (est, lo, hi) = recs[last - 7..last].iter().fold((0.0, 0.0, 0.0), |acc, r| {
+            let (e, l, h) = nc.adjusted(r.date, r.new_cases.unwrap_or(0));
+            (acc.0 + e / 7.0, acc.1 + l / 7.0, acc.2 + h / 7.0)
+        });
+        println!(
+            "Nowcast 7 day avg {:.0} cases/day (80% range {:.0}-{:.0}) once late reports are in  ",
+            est, lo, hi
+        );
+    }
+
+    let indicators = levels::Indicators::from_records(recs, population);
+    let rule_sets = levels::rule_sets(cfg);
+    match (indicators, levels::in_force(&rule_sets, recs[last].date)) {
+        (Some(ind), Some(rule_set)) => match rule_set.assess(&ind) {
+            Some(assessment) => println!("{assessment}  "),
+            None => println!("{}: not enough data  ", rule_set.title),
+        },
+        (None, Some(rule_set)) => println!("{}: not enough data  ", rule_set.title),
+        (_, None) => {}
+    }
 
     if let Some(pop) = population {
-        let cases_week_100k = ((highest_cases * 7) as f32) / ((pop as f32) / 100000.0);
-        let level = match cases_week_100k {
-            x if (0.0..10.0).contains(&x) => "Low",
-            x if (10.0..50.0).contains(&x) => "Moderate",
-            x if (50.0..100.0).contains(&x) => "Substantial",
-            x if (100.0..).contains(&x) => "High",
-            _ => "ERROR",
+        let cases_week: u32 = recs[last - 7..last].iter().map(|x| x.new_cases.unwrap_or(0)).sum();
+        let per_100k = |v: Option<(u32, i32, bool)>| match v {
+            Some((n, _, _)) => format!("{:.1}", population::per_100k(n as f64, pop)),
+            None => "-".to_string(),
         };
-        println!("Transmission risk: {level}  ");
+        println!(
+            "Per 100k: {:.1} cases in the last week, {} hospitalized, {} in ICU  ",
+            population::per_100k(cases_week as f64, pop),
+            per_100k(hosp),
+            per_100k(icu)
+        );
     }
 }
 
-fn count_cases(filename: &str, jurisdiction: &str) -> Result<u32> {
-    Ok(csvrecs::<CasesRecord>(filename)?
+fn count_cases(
+    cfg: &Config,
+    store: Option<&store::Store>,
+    day: &chrono::DateTime<chrono::Local>,
+    jurisdiction: &str,
+) -> Result<u32> {
+    if let Some(store) = store {
+        if let Some(total) = store.case_total(vintage_date(day), jurisdiction)? {
+            return Ok(total);
+        }
+    }
+    Ok(load_cases(cfg, None, day)?
         .iter()
         .filter(|x| x.county == jurisdiction)
         .map(|x| x.new_cases.unwrap_or(0))
         .sum())
 }
 
-const CSV_DATE_FORMAT: &str = "%Y%m%d";
+fn cases_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.csv_dir.join(day.format(&cfg.files.cases).to_string())
+}
+
+fn cases_raw_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.csv_dir.join(day.format(&cfg.files.cases_raw).to_string())
+}
+
+fn hosps_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.csv_dir.join(day.format(&cfg.files.hospitalizations).to_string())
+}
 
-fn cases_file(day: &chrono::DateTime<chrono::Local>) -> String {
-    let datestamp = day.format(CSV_DATE_FORMAT);
-    format!("{}/{}_{}.csv", CSVDIR, CASES_PREFIX, datestamp)
+fn vax_doses_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.csv_dir.join(day.format(&cfg.files.vax_doses).to_string())
 }
 
-fn hosps_file(day: &chrono::DateTime<chrono::Local>) -> String {
-    let datestamp = day.format(CSV_DATE_FORMAT);
-    format!("{}/{}_{}.csv", CSVDIR, HOSP_PREFIX, datestamp)
+fn vax_coverage_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.vax_dir.join(day.format(&cfg.files.vax_coverage).to_string())
 }
 
-fn tests_file(day: &chrono::DateTime<chrono::Local>) -> String {
-    let datestamp = day.format("%m-%d-%Y");
-    format!("{}/{}.csv", TESTS_DIR, datestamp)
+fn tests_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.tests_dir.join(day.format(&cfg.files.tests).to_string())
 }
 
-fn pcr_tests_file(day: &chrono::DateTime<chrono::Local>) -> String {
-    let datestamp = day.format(CSV_DATE_FORMAT);
-    format!("{}/{}_{}.csv", CSVDIR, PCR_PREFIX, datestamp)
+fn pcr_tests_file(cfg: &Config, day: &chrono::DateTime<chrono::Local>) -> PathBuf {
+    cfg.csv_dir.join(day.format(&cfg.files.pcr_tests).to_string())
 }
 
 fn count_case_delta(
+    cfg: &Config,
+    store: Option<&store::Store>,
     to_date: &chrono::DateTime<chrono::Local>,
     from_date: &chrono::DateTime<chrono::Local>,
     jurisdiction: &str,
 ) -> Result<i32> {
-    let to_file = cases_file(to_date);
-    let from_file = cases_file(from_date);
+    let to = count_cases(cfg, store, to_date, jurisdiction)?;
+    let from = count_cases(cfg, store, from_date, jurisdiction)?;
+    Ok(to as i32 - from as i32)
+}
+
+fn vintage_date(day: &chrono::DateTime<chrono::Local>) -> chrono::NaiveDate {
+    day.naive_local().date()
+}
+
+/// Midday local time on `date`, for the functions that take a report day.
+fn day_of(date: chrono::NaiveDate) -> chrono::DateTime<chrono::Local> {
+    let n = date.and_time(chrono::NaiveTime::from_hms_milli(12, 34, 56, 789));
+    chrono::Local.from_local_datetime(&n).unwrap()
+}
 
-    Ok(count_cases(&to_file, jurisdiction)? as i32 - count_cases(&from_file, jurisdiction)? as i32)
+/// The cases snapshot for `day`, from the store if it has it, else the CSV archive.
+fn load_cases(
+    cfg: &Config,
+    store: Option<&store::Store>,
+    day: &chrono::DateTime<chrono::Local>,
+) -> Result<Vec<CasesRecord>> {
+    if let Some(store) = store {
+        if let Some(recs) = store.cases(vintage_date(day))? {
+            return Ok(recs);
+        }
+    }
+    csvrecs::<CasesRecord>(cfg, cases_file(cfg, day))
 }
 
-fn get_all_records(today: &chrono::DateTime<chrono::Local>) -> Result<Vec<HospitalRecord>> {
-    let case_records = csvrecs::<CasesRecord>(&cases_file(today))?;
+/// The hospitalization snapshot for `day`, from the store if it has it, else the CSV archive.
+fn load_hospitals(
+    cfg: &Config,
+    store: Option<&store::Store>,
+    day: &chrono::DateTime<chrono::Local>,
+) -> Result<Vec<HospitalRecord>> {
+    if let Some(store) = store {
+        if let Some(recs) = store.hospitals(vintage_date(day))? {
+            return Ok(recs);
+        }
+    }
+    csvrecs::<HospitalRecord>(cfg, hosps_file(cfg, day))
+}
+
+fn get_all_records(
+    cfg: &Config,
+    store: Option<&store::Store>,
+    today: &chrono::DateTime<chrono::Local>,
+) -> Result<Vec<HospitalRecord>> {
+    let case_records = load_cases(cfg, store, today)?;
     let mut casehash = std::collections::HashMap::new();
     for c in case_records {
         casehash.insert(format!("{}{}", c.county, c.date), c);
     }
 
-    let mut all_records = csvrecs::<HospitalRecord>(&hosps_file(today))?;
+    let mut all_records = load_hospitals(cfg, store, today)?;
     for r in &mut all_records {
         let key = format!("{}{}", r.county, r.date);
         if let Some(caserec) = casehash.get(&key) {
@@ -364,12 +740,30 @@ fn get_all_records(today: &chrono::DateTime<chrono::Local>) -> Result<Vec<Hospit
     Ok(all_records)
 }
 
+/// Days of records a jurisdiction needs for its stats and plots; the 60 day
+/// plot is the longest look back.
+const MIN_RECORDS: usize = 60;
+
+/// Whether `recs` has enough history to report on, saying so if not.
+fn enough_records(recs: &[HospitalRecord], jurisdiction: &str) -> bool {
+    if recs.len() < MIN_RECORDS {
+        println!(
+            "Skipping {jurisdiction}: {} days of records, need {MIN_RECORDS} (check the name in the config)  ",
+            recs.len()
+        );
+        return false;
+    }
+    true
+}
+
 fn analyze(
+    cfg: &Config,
     all_records: &[HospitalRecord],
     jurisdiction: &str,
     jurisdiction_full: &str,
     new_cases: Option<i32>,
     population: Option<u32>,
+    nowcast: Option<&nowcast::Nowcast>,
 ) {
     let county_records: Vec<HospitalRecord> = all_records
         .iter()
@@ -377,100 +771,98 @@ fn analyze(
         .sorted_by_key(|x| x.date)
         .cloned()
         .collect();
+    if !enough_records(&county_records, jurisdiction) {
+        return;
+    }
 
     if let Some(new_cases) = new_cases {
         println!("{jurisdiction} reports {new_cases} new cases.  ");
     }
-    let (icunorm, icunormfree) = if jurisdiction == "Pennsylvania" {
-        (4200, 1040)
+    let icu_baseline = icu::Baseline::from_records(cfg, &county_records);
+    let weekday = if cfg.weekday.enabled {
+        weekday::WeekdayEffect::estimate(&county_records, cfg.weekday.weeks)
     } else {
-        (560, 180)
+        None
     };
-    printstats(&county_records, icunorm, icunormfree, population);
-    //printstats(&county_records, 560, 180);
-    if let Err(e) = plot_jurisdiction(&county_records, jurisdiction_full, false, false) {
+    let weekday = weekday.as_ref();
+    printstats(cfg, &county_records, icu_baseline, population, nowcast, weekday);
+    if cfg.rt.enabled {
+        let rt = rt::estimate(&cfg.rt, &county_records, nowcast);
+        if let Some(p) = rt.last() {
+            println!(
+                "Rt {:.2} (95% CrI {:.2}-{:.2}) for the {} days to {}  ",
+                p.mean, p.lo, p.hi, cfg.rt.window, p.date
+            );
+        }
+        if let Err(e) = rt::plot_rt(&rt, jurisdiction_full) {
+            println!("Error plotting Rt for {}: {:?}", jurisdiction, e);
+        }
+    }
+    if cfg.ratio.enabled {
+        if let Some(ratios) = ratio::CaseRatios::compute(cfg, &county_records, nowcast) {
+            ratios.print();
+            if let Err(e) = ratio::plot_ratios(&ratios, jurisdiction_full) {
+                println!("Error plotting case ratios for {}: {:?}", jurisdiction, e);
+            }
+        }
+    }
+    if let Err(e) = plot_jurisdiction(cfg, &county_records, jurisdiction_full, false, false, nowcast, weekday) {
         println!("Error plotting jurisdiction {}: {:?}", jurisdiction, e);
     }
     let jurisdiction_60d = format!("{}_60days", jurisdiction_full);
     let county_60d = &county_records[county_records.len() - 60..];
-    if let Err(e) = plot_jurisdiction(county_60d, &jurisdiction_60d, true, false) {
+    if let Err(e) = plot_jurisdiction(cfg, county_60d, &jurisdiction_60d, true, false, nowcast, weekday) {
         println!("Error plotting jurisdiction {}: {:?}", jurisdiction_60d, e);
     }
     let jurisdiction_trunc = format!("{}_trunc", jurisdiction_full);
-    if let Err(e) = plot_jurisdiction(&county_records, &jurisdiction_trunc, false, true) {
+    if let Err(e) = plot_jurisdiction(cfg, &county_records, &jurisdiction_trunc, false, true, nowcast, weekday) {
         println!("Error plotting jurisdiction {}: {:?}", jurisdiction_trunc, e);
     }
 }
 
-fn cleanup<I: Iterator<Item = Option<u32>>>(vals: I) -> Vec<u32> {
-    let v: Vec<Option<u32>> = vals.collect();
-    let mut result = Vec::new();
-    for i in 0..v.len() {
-        match v[i] {
-            Some(x) => result.push(x),
-            None => {
-                if i == 0 || i == (v.len() - 1) {
-                    result.push(0);
-                } else {
-                    result.push((v[i - 1].unwrap_or(0) + v[i + 1].unwrap_or(0)) / 2);
-                }
-            }
-        }
-    }
-    result
-}
-
-fn reportcovid(today: &chrono::DateTime<chrono::Local>) -> Result<()> {
+fn reportcovid(cfg: &Config, today: &chrono::DateTime<chrono::Local>) -> Result<()> {
     let yesterday = *today - chrono::Duration::days(7); // now last week
 
-    // This is all inefficient but we're fast enough, so ignore.
-    let new_cases_allegheny = count_case_delta(today, &yesterday, "Allegheny")?;
-    let new_cases_state = count_case_delta(today, &yesterday, "Pennsylvania")?;
-    let extra_county = "Philadelphia";
-    let extra_county_name = "Philadelphia County";
-    let new_cases_philly = count_case_delta(today, &yesterday, extra_county)?;
-
-    let all_records = get_all_records(today)?;
+    let store = store::Store::open_existing(cfg)?;
+    let all_records = get_all_records(cfg, store.as_ref(), today)?;
+    let populations = population::Populations::load(cfg)?;
+    let history = if cfg.nowcast.enabled {
+        let names: Vec<&str> = cfg.jurisdictions.iter().map(|j| j.name.as_str()).collect();
+        nowcast::load_history(cfg, store.as_ref(), today, &names)
+    } else {
+        nowcast::History::new()
+    };
 
     println!();
-    println!("## Allegheny County");
-    analyze(
-        &all_records,
-        "Allegheny",
-        "Allegheny County",
-        Some(new_cases_allegheny),
-        Some(1213570),
-    );
-    println!("CDC Community level: ");
-    println!("\n{{{{ allegheny() }}}}\n\n");
-
-    println!("## Pennsylvania");
-    analyze(
-        &all_records,
-        "Pennsylvania",
-        "Pennsylvania",
-        Some(new_cases_state),
-        Some(12964056),
-    );
-
-    println!("\n{{{{ pennsylvania() }}}}\n\n");
-    analyze(
-        &all_records,
-        extra_county,
-        extra_county_name,
-        Some(new_cases_philly),
-        Some(1585480),
-    );
-
-    let _res = testreport(today);
+    for j in &cfg.jurisdictions {
+        // This is all inefficient but we're fast enough, so ignore.
+        let new_cases = count_case_delta(cfg, store.as_ref(), today, &yesterday, &j.name)?;
+        if j.heading {
+            println!("## {}", j.display_name());
+        }
+        analyze(
+            cfg,
+            &all_records,
+            &j.name,
+            j.display_name(),
+            Some(new_cases),
+            populations.of(j),
+            nowcast::Nowcast::estimate(cfg, &history, &j.name).as_ref(),
+        );
+        if let Some(shortcode) = &j.shortcode {
+            println!("\n{{{{ {shortcode}() }}}}\n\n");
+        }
+    }
+
+    let _res = testreport(cfg, today);
     println!();
 
     println!("## Vaccinations");
-    println!("Allegheny County daily vaccines move to X 1st+2nd doses, Y booster doses/day (5 day lagged 7 day avg)\n");
-    println!("Fully vaccinated %s:\n");
-    println!("Allegheny County: X%, Y% of those are boosted, Z% of boosted got 4th dose.");
+    if let Err(e) = vaxreport(cfg, today) {
+        println!("Error creating vaccination report: {:#}", e);
+    }
     println!();
-    hospitalizations(&all_records);
+    hospitalizations(cfg, &all_records);
 
 
 
@@ -488,6 +880,9 @@ fn reportcovid(today: &chrono::DateTime<chrono::Local>) -> Result<()> {
             .sorted_by_key(|x| x.date)
             .cloned()
             .collect();
+        if !enough_records(&recs, &j) {
+            continue;
+        }
         let last = recs.len() - 1;
         let mut step0 = 0.0;
         let mut step7 = 0.0;
@@ -526,13 +921,16 @@ fn cmpname(o: std::cmp::Ordering) -> &'static str {
     }
 }
 
-fn hospitalizations(all_records: &[HospitalRecord]) {
+fn hospitalizations(cfg: &Config, all_records: &[HospitalRecord]) {
     let mut pa_records = all_records
         .iter()
-        .filter(|x| x.county == "Pennsylvania")
+        .filter(|x| x.county == cfg.state)
         .cloned()
         .collect::<Vec<_>>();
     pa_records.sort_by_key(|r| r.date);
+    if !enough_records(&pa_records, &cfg.state) {
+        return;
+    }
     let t = pa_records.len() - 1;
     let today = &pa_records[t];
     let today_used = today.med_surg_total.unwrap() - today.med_surg_available.unwrap();
@@ -552,136 +950,65 @@ fn hospitalizations(all_records: &[HospitalRecord]) {
 
 }
 
-fn dayreport() -> Result<()> {
-    println!("Day report");
-    let today = chrono::Local::now();
-    let mut all_records: Vec<HospitalRecord> = get_all_records(&today)?
-        .iter()
-        .filter(|x| x.county == "Pennsylvania")
-        .cloned()
-        .collect();
-    all_records.sort_by_key(|r| r.date);
-    let nr = all_records.len();
-    let num_windows = 16; // analyze 12 weeks of data
-    let analysis_length = num_windows * 7;
-    let mut dayper: Vec<f32> = vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
-    all_records[nr - analysis_length - 1..nr - 1]
-        .chunks(7)
-        .for_each(|window| {
-            let tot_cases = window
-                .iter()
-                .map(|r| r.new_cases.unwrap_or(0) as f32)
-                .sum::<f32>();
-            for w in window {
-                let wd = w.date.weekday().num_days_from_monday() as usize; // mon = 0
-                println!("w date {} nc: {:?}", w.date, w.new_cases);
-                dayper[wd] +=
-                    (w.new_cases.unwrap_or(0) as f32) / (tot_cases as f32 * num_windows as f32);
-            }
-        });
-    println!("Dayper: {:?}", dayper);
-    Ok(())
-}
-
 #[derive(Debug, StructOpt)]
 #[structopt(name = "covidreport", about = "Analyze data from PA covid feeds")]
 struct Opt {
-    #[structopt(short, long)]
+    #[structopt(short, long, help = "Same as the dayreport subcommand with its defaults")]
     dayreport: bool,
     #[structopt(short, long)]
     agereport: bool,
     #[structopt(long, help = "Analyze for specified date (%Y-%m-%d format)")]
     date: Option<String>,
+    #[structopt(long, help = "Fail on any CSV row that doesn't parse instead of dropping it")]
+    strict: bool,
+    #[structopt(long, help = "How to fill missing hospital values: none, linear or locf (default: from config)")]
+    gap_policy: Option<gaps::GapPolicy>,
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Config file (default: the first of $COVIDREPORT_CONFIG, ./covidreport.toml, \
+                $XDG_CONFIG_HOME/covidreport/config.toml and ~/.config/covidreport/config.toml that exists)"
+    )]
+    config: Option<PathBuf>,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-fn get_all_testday_records(day: &chrono::DateTime<chrono::Local>) -> Result<Vec<TestRecord>> {
-    let fname = tests_file(day);
-    let case_records = csvrecs::<TestRecord>(&fname)?;
-    Ok(case_records)
-}
-
-fn plot_ages(recs: &[TestRecord], truncate: bool) -> Result<()> {
-    let (img_path, max_cases) = if truncate {
-        ("case_ages_truncated.png", 150)
-    } else { ("case_ages.png", 750)
-        };
-    let dates: Vec<chrono::NaiveDate> = recs.iter().map(|x| x.report_date).collect();
-    let root = BitMapBackend::new(&img_path, (1024, 768)).into_drawing_area();
-    root.fill(&WHITE)?;
-    let max_date = *(dates.iter().max().unwrap()) + chrono::Duration::days(1);
-    let min_date = *(dates.iter().min().unwrap());
-    let ndays = (max_date - min_date).num_days();
-
-    // Create vectors of cases/day grouped by age group;
-    // need to partition by age group and then sum counts by day where it's a case
-
-    let mut agebins: HashMap<String, Vec<u32>> = HashMap::new();
-    let ages = vec![
-        "0 to 9", "10 to 19", "20 to 29", "30 to 39", "40 to 49", "50 to 59", "60 to 69", "70+",
-        "unknown",
-    ];
-
-    for age in &ages {
-        agebins.insert(age.to_string(), vec![0; (ndays + 1) as usize]);
-    }
-    for rec in recs
-        .iter()
-        .filter(|x| x.case_status == "Probable" || x.case_status == "Confirmed")
-    {
-        let case_nday = (rec.report_date - min_date).num_days();
-        agebins.get_mut(&rec.age_bucket.to_lowercase()).unwrap()[case_nday as usize] += 1;
-    }
-
-    let dates7day = (7..ndays + 1).map(|x| min_date + chrono::Duration::days(x));
-
-    let mut chart = ChartBuilder::on(&root)
-        .margin(10)
-        .caption(
-            "Cases by age group and date: Allegheny County",
-            ("sans-serif", 40),
-        )
-        .set_label_area_size(LabelAreaPosition::Left, 60)
-        .set_label_area_size(LabelAreaPosition::Right, 60)
-        .set_label_area_size(LabelAreaPosition::Bottom, 40)
-        .build_cartesian_2d(min_date..max_date, 0u32..max_cases)?;
-    chart.configure_mesh().x_labels(9).x_desc("Date").draw()?;
-    for (color, age) in ages.iter().take(8).enumerate() {
-        let cases7day: Vec<u32> = agebins
-            .get(age.to_owned())
-            .unwrap()
-            .windows(7)
-            .map(|w| ((w.iter().sum::<u32>() as f64) / (w.len() as f64)).round() as u32)
-            .collect();
-        let datecases7day = dates7day.to_owned().zip(cases7day);
-        let style = plotters::style::ShapeStyle {
-            color: plotters::style::Palette99::pick(color).to_rgba(),
-            filled: true,
-            stroke_width: 1,
-        };
-        chart
-            .draw_series(LineSeries::new(datecases7day, style.clone()))?
-            .label(age.to_owned())
-            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
-    }
-    chart
-        .configure_series_labels()
-        .border_style(&BLACK)
-        .position(SeriesLabelPosition::UpperLeft)
-        .draw()?;
-
-    Ok(())
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Archive the day's raw downloads under their dated names
+    Ingest(ingest::IngestOpt),
+    /// Show which expected columns a CSV file has, lacks, or adds
+    SchemaCheck(schema::SchemaCheckOpt),
+    /// Backfill the snapshot store from the dated CSV archive
+    ImportArchive(store::ImportOpt),
+    /// Show how two vintages of the feeds differ, date by date
+    Revisions(revisions::RevisionsOpt),
+    /// Show each weekday's share of the weekly cases
+    Dayreport(weekday::DayreportOpt),
+    /// Break cases and their outcomes down by a demographic field
+    Breakdown(breakdown::BreakdownOpt),
+    /// Show the share of cases hospitalized, in the ICU or ventilated, by age and month
+    Severity(severity::SeverityOpt),
+    /// Show how many days tests take from collection to report, by week
+    Lags(reportlag::LagsOpt),
+    /// Plot cases by collection date, shading the days still filling in
+    Epicurve(epicurve::EpicurveOpt),
 }
 
-fn agereport(today: &chrono::DateTime<chrono::Local>) -> Result<()> {
+fn agereport(cfg: &Config, today: &chrono::DateTime<chrono::Local>) -> Result<()> {
     println!("Calculating age report!");
-    let mut all_records: Vec<TestRecord> = get_all_testday_records(today)?
-        .iter()
-        .filter(|x| x.report_date >= chrono::NaiveDate::from_ymd(2021, 1, 1))
-        .cloned()
-        .collect();
-    all_records.sort_by_key(|x| x.report_date);
-    plot_ages(&all_records, false)?;
-    plot_ages(&all_records, true)
+    let summary = testrecs::summarize_tests(cfg, today, chrono::NaiveDate::from_ymd(2021, 1, 1))?;
+    summary.positivity.print();
+    summary.lags.print_warning(&cfg.report_lag);
+    if let Err(e) = positivity::plot_positivity(&summary.positivity) {
+        println!("Error plotting test positivity: {:?}", e);
+    }
+    let cases_by_age = summary.groups.get(breakdown::Field::Age, breakdown::Outcome::Cases);
+    let title = breakdown::title(breakdown::Field::Age, breakdown::Outcome::Cases);
+    let ages = Some(&breakdown::AGE_BUCKETS[..]);
+    breakdown::plot(cases_by_age, &title, "case_ages.png", ages, Some(750.0), None)?;
+    breakdown::plot(cases_by_age, &title, "case_ages_truncated.png", ages, Some(150.0), None)
 }
 
 #[allow(dead_code)]
@@ -694,39 +1021,190 @@ struct PCR_tests {
     new_tests: i64,
 }
 
-fn count_tests(tests_file: &str) -> Result<i64> {
-    Ok(csvrecs::<PCR_tests>(tests_file)?
+impl Schema for PCR_tests {
+    const KIND: &'static str = "pcr-tests";
+    const COLUMNS: &'static [&'static str] = &["Date", "New PCR Tests"];
+}
+
+fn count_tests(cfg: &Config, tests_file: impl AsRef<Path>) -> Result<i64> {
+    Ok(csvrecs::<PCR_tests>(cfg, tests_file)?
         .iter()
         .map(|x| x.new_tests)
         .sum())
 }
 
-fn testreport(today: &chrono::DateTime<chrono::Local>) -> Result<()> {
+/// The by-day feed fills in for a few days after the fact, so the dose rates
+/// skip the most recent `VAX_LAG_DAYS` before averaging.
+const VAX_LAG_DAYS: usize = 5;
+
+fn vaxreport(cfg: &Config, today: &chrono::DateTime<chrono::Local>) -> Result<()> {
+    let day_records = csvrecs::<VaxDayRecord>(cfg, vax_doses_file(cfg, today))?;
+    let coverage_records = csvrecs::<VaxCoverageRecord>(cfg, vax_coverage_file(cfg, today))?;
+
+    // The vaccination feeds only have counties; the state is their sum.
+    let in_jurisdiction = |county: &str, jurisdiction: &str| {
+        jurisdiction == cfg.state || county == jurisdiction
+    };
+
+    for j in &cfg.jurisdictions {
+        let by_date = day_records
+            .iter()
+            .filter(|r| in_jurisdiction(&r.county, &j.name))
+            .map(|r| {
+                (
+                    r.date,
+                    r.partially_covered.unwrap_or(0) + r.fully_covered.unwrap_or(0),
+                    // First boosters only, as the 7dayvax extract has always counted
+                    r.first_booster.unwrap_or(0),
+                )
+            })
+            .into_grouping_map_by(|(date, _, _)| *date)
+            .fold((0, 0), |(doses, boosters), _, (_, d, b)| (doses + d, boosters + b));
+        let days: Vec<(u32, u32)> = by_date
+            .into_iter()
+            .sorted_by_key(|(date, _)| *date)
+            .map(|(_, counts)| counts)
+            .collect();
+        if days.len() >= VAX_LAG_DAYS + 7 {
+            let window = &days[days.len() - VAX_LAG_DAYS - 7..days.len() - VAX_LAG_DAYS];
+            let doses = window.iter().map(|(d, _)| *d as f32).sum::<f32>() / 7.0;
+            let boosters = window.iter().map(|(_, b)| *b as f32).sum::<f32>() / 7.0;
+            println!(
+                "{} daily vaccines move to {:.0} 1st+2nd doses, {:.0} booster doses/day ({} day lagged 7 day avg)  ",
+                j.display_name(),
+                doses,
+                boosters,
+                VAX_LAG_DAYS
+            );
+        }
+    }
+    println!();
+
+    println!("Fully vaccinated %s:\n");
+    let populations = population::Populations::load(cfg)?;
+    for j in &cfg.jurisdictions {
+        let pop = match populations.of(j) {
+            Some(pop) => pop,
+            None => continue,
+        };
+        let (fully, boosted, second_boost) = coverage_records
+            .iter()
+            .filter(|r| in_jurisdiction(&r.county, &j.name))
+            .fold((0u64, 0u64, 0u64), |(f, b, s), r| {
+                (
+                    f + r.fully_covered.unwrap_or(0) as u64,
+                    b + r.first_booster.unwrap_or(0) as u64,
+                    s + r.second_booster.unwrap_or(0) as u64,
+                )
+            });
+        if fully == 0 {
+            continue;
+        }
+        println!(
+            "{}: {:.1}%, {:.1}% of those are boosted, {:.1}% of boosted got 4th dose.  ",
+            j.display_name(),
+            fully as f64 * 100.0 / pop as f64,
+            boosted as f64 * 100.0 / fully as f64,
+            if boosted > 0 { second_boost as f64 * 100.0 / boosted as f64 } else { 0.0 },
+        );
+    }
+    Ok(())
+}
+
+fn testreport(cfg: &Config, today: &chrono::DateTime<chrono::Local>) -> Result<()> {
     let yesterday = *today - chrono::Duration::days(1);
-    let yesterday_tests = count_tests(&pcr_tests_file(&yesterday))?;
-    let today_tests = count_tests(&pcr_tests_file(today))?;
+    let yesterday_tests = count_tests(cfg, pcr_tests_file(cfg, &yesterday))?;
+    let today_tests = count_tests(cfg, pcr_tests_file(cfg, today))?;
     println!("Today's results reflect {} new PCR test results", today_tests - yesterday_tests);
     Ok(())
 }
 
 fn main() {
     let opt = Opt::from_args();
+    let mut cfg = match Config::load(opt.config.as_deref()) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            println!("Error loading config: {:#}", e);
+            return;
+        }
+    };
+    if opt.strict {
+        cfg.parse.mode = parse::ParseMode::Strict;
+    }
+    if let Some(policy) = opt.gap_policy {
+        cfg.gap_policy = policy;
+    }
     let today = if let Some(datestr) = opt.date {
-        let n = chrono::NaiveDate::parse_from_str(&datestr, "%Y-%m-%d").unwrap();
-        let n = n.and_time(chrono::NaiveTime::from_hms_milli(12, 34, 56, 789));
-        chrono::Local.from_local_datetime(&n).unwrap()
+        day_of(chrono::NaiveDate::parse_from_str(&datestr, "%Y-%m-%d").unwrap())
     } else {
         chrono::Local::now()
     };
+    match &opt.cmd {
+        Some(Command::Ingest(ingest_opt)) => {
+            if let Err(e) = ingest::ingest(&cfg, &today, ingest_opt) {
+                println!("Error ingesting data: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::SchemaCheck(check_opt)) => {
+            if let Err(e) = schema::schema_check(&cfg, check_opt) {
+                println!("Error checking schema: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::ImportArchive(import_opt)) => {
+            if let Err(e) = store::import_archive(&cfg, import_opt) {
+                println!("Error importing archive: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::Revisions(revisions_opt)) => {
+            if let Err(e) = revisions::revisions(&cfg, &today, revisions_opt) {
+                println!("Error creating revisions report: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::Breakdown(breakdown_opt)) => {
+            if let Err(e) = breakdown::breakdown(&cfg, &today, breakdown_opt) {
+                println!("Error creating breakdown: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::Severity(severity_opt)) => {
+            if let Err(e) = severity::severity(&cfg, &today, severity_opt) {
+                println!("Error creating severity report: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::Lags(lags_opt)) => {
+            if let Err(e) = reportlag::lags(&cfg, &today, lags_opt) {
+                println!("Error creating lag report: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::Epicurve(epicurve_opt)) => {
+            if let Err(e) = epicurve::epicurve(&cfg, &today, epicurve_opt) {
+                println!("Error creating epicurve: {:#}", e);
+            }
+            return;
+        }
+        Some(Command::Dayreport(dayreport_opt)) => {
+            if let Err(e) = weekday::dayreport(&cfg, &today, dayreport_opt) {
+                println!("Error creating dayreport: {:#}", e);
+            }
+            return;
+        }
+        None => {}
+    }
     if opt.agereport {
-        if let Err(e) = agereport(&today) {
+        if let Err(e) = agereport(&cfg, &today) {
             println!("Error creating agereport: {}", e);
         }
         return;
     }
     if opt.dayreport {
-        if let Err(e) = dayreport() {
-            println!("Error creating dayreport: {}", e);
+        if let Err(e) = weekday::dayreport(&cfg, &today, &weekday::DayreportOpt::default()) {
+            println!("Error creating dayreport: {:#}", e);
         };
         return;
     }
@@ -735,8 +1213,10 @@ fn main() {
     println!("date = {todaystr}");
     println!("+++\n");
     println!("# Allegheny County & Pennsylvania #covid hospitalization & variants thread for {}\n", todaystr);
-    let res = reportcovid(&today);
+    let res = reportcovid(&cfg, &today);
     println!("Res: {:#?}", res);
-    let _res = testreport(&today);
-    let _res = agereport(&today);
+    let _res = testreport(&cfg, &today);
+    let _res = agereport(&cfg, &today);
+    println!();
+    parse::print_reports();
 }
@@ -0,0 +1,245 @@
+//! Nowcasting of the most recent, still-incomplete days of the cases feed.
+//!
+//! Cases keep trickling in for a week or two after a date is first reported,
+//! so the tail of every snapshot is undercounted.  From the archive of past
+//! snapshots we measure what fraction of a date's eventual count had shown up
+//! `k` days after it, and scale up today's recent values accordingly.
+
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::Config;
+use crate::store::Store;
+use crate::{load_cases, vintage_date};
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NowcastOptions {
+    pub enabled: bool,
+    /// How many days of past snapshots to learn the reporting delay from
+    pub history_days: i64,
+    /// Nowcast dates fewer than this many days old
+    pub max_delay: i64,
+    /// A date's count is taken as final once it's this many days old
+    pub complete_after: i64,
+}
+
+impl Default for NowcastOptions {
+    fn default() -> Self {
+        NowcastOptions {
+            enabled: true,
+            history_days: 60,
+            max_delay: 14,
+            complete_after: 28,
+        }
+    }
+}
+
+/// Case counts per jurisdiction, per snapshot date, per data date.
+pub type History = HashMap<String, BTreeMap<chrono::NaiveDate, BTreeMap<chrono::NaiveDate, u32>>>;
+
+/// Load the last `history_days` snapshots of the cases feed for `jurisdictions`.
+/// Snapshots missing from the archive are skipped.
+pub fn load_history(
+    cfg: &Config,
+    store: Option<&Store>,
+    today: &chrono::DateTime<chrono::Local>,
+    jurisdictions: &[&str],
+) -> History {
+    let mut history = History::new();
+    for back in 0..=cfg.nowcast.history_days {
+        let day = *today - chrono::Duration::days(back);
+        let recs = match load_cases(cfg, store, &day) {
+            Ok(recs) => recs,
+            Err(_) => continue,
+        };
+        for r in recs {
+            if jurisdictions.contains(&r.county.as_str()) {
+                history
+                    .entry(r.county)
+                    .or_default()
+                    .entry(vintage_date(&day))
+                    .or_default()
+                    .insert(r.date, r.new_cases.unwrap_or(0));
+            }
+        }
+    }
+    history
+}
+
+/// Share of a date's final count that had been reported `k` days after it.
+#[derive(Debug, Clone, Copy)]
+struct Completeness {
+    mean: f64,
+    /// 10th and 90th percentiles across the dates sampled
+    lo: f64,
+    hi: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NowcastPoint {
+    pub estimate: f64,
+    pub lo: f64,
+    pub hi: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nowcast {
+    points: BTreeMap<chrono::NaiveDate, NowcastPoint>,
+}
+
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+    sorted[idx]
+}
+
+impl Nowcast {
+    /// Estimate the reporting-delay distribution for `jurisdiction` from its
+    /// snapshot history and nowcast the recent days of the latest snapshot.
+    /// None if there isn't enough history to say anything.
+    pub fn estimate(cfg: &Config, history: &History, jurisdiction: &str) -> Option<Nowcast> {
+        let opts = &cfg.nowcast;
+        let vintages = history.get(jurisdiction)?;
+        let (latest_vintage, latest) = vintages.iter().next_back()?;
+
+        let mut completeness = Vec::new();
+        for k in 0..opts.max_delay {
+            let mut observed_sum = 0.0;
+            let mut final_sum = 0.0;
+            let mut ratios = Vec::new();
+            for (date, &final_count) in latest.range(..=*latest_vintage - chrono::Duration::days(opts.complete_after)) {
+                let early = vintages
+                    .get(&(*date + chrono::Duration::days(k)))
+                    .and_then(|v| v.get(date));
+                if let (Some(&early), true) = (early, final_count > 0) {
+                    observed_sum += early as f64;
+                    final_sum += final_count as f64;
+                    ratios.push(early as f64 / final_count as f64);
+                }
+            }
+            if ratios.len() < 3 || observed_sum == 0.0 {
+                completeness.push(None);
+                continue;
+            }
+            ratios.sort_by(|a, b| a.total_cmp(b));
+            completeness.push(Some(Completeness {
+                mean: observed_sum / final_sum,
+                lo: percentile(&ratios, 0.1),
+                hi: percentile(&ratios, 0.9),
+            }));
+        }
+
+        let points: BTreeMap<_, _> = latest
+            .range(*latest_vintage - chrono::Duration::days(opts.max_delay - 1)..)
+            .filter_map(|(date, &observed)| {
+                let k = (*latest_vintage - *date).num_days() as usize;
+                let c = (*completeness.get(k)?)?;
+                let scale = |share: f64| observed as f64 / share.max(0.01);
+                Some((
+                    *date,
+                    NowcastPoint {
+                        estimate: scale(c.mean),
+                        // Low completeness means a high eventual count
+                        lo: scale(c.hi).min(scale(c.mean)),
+                        hi: scale(c.lo).max(scale(c.mean)),
+                    },
+                ))
+            })
+            .collect();
+        if points.is_empty() {
+            None
+        } else {
+            Some(Nowcast { points })
+        }
+    }
+
+    pub fn get(&self, date: chrono::NaiveDate) -> Option<&NowcastPoint> {
+        self.points.get(&date)
+    }
+
+    /// Estimate and range for `date`: the nowcast if there is one, otherwise
+    /// the observed count for all three.
+    pub fn adjusted(&self, date: chrono::NaiveDate, observed: u32) -> (f64, f64, f64) {
+        match self.get(date) {
+            Some(p) => (p.estimate, p.lo, p.hi),
+            None => (observed as f64, observed as f64, observed as f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLACE: &str = "Allegheny";
+    /// Last day of data, and the latest snapshot
+    const LAST: i64 = 30;
+
+    fn day(n: i64) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd(2022, 1, 1) + chrono::Duration::days(n)
+    }
+
+    fn cfg() -> Config {
+        let mut cfg = Config::default();
+        cfg.nowcast.max_delay = 3;
+        cfg.nowcast.complete_after = 10;
+        cfg
+    }
+
+    /// Daily snapshots from `first` to LAST of days 0 onwards, each day
+    /// eventually 100 cases, `share(k, d)` of them in by `k` days after day `d`.
+    fn history(first: i64, share: impl Fn(i64, i64) -> f64) -> History {
+        let mut vintages = BTreeMap::new();
+        for v in first..=LAST {
+            let counts = (0..=v).map(|d| (day(d), (100.0 * share(v - d, d)).round() as u32)).collect();
+            vintages.insert(day(v), counts);
+        }
+        [(PLACE.to_string(), vintages)].into_iter().collect()
+    }
+
+    /// Half in the same day, 80% the next, all by the day after.
+    fn steady(k: i64, _: i64) -> f64 {
+        [0.5, 0.8, 1.0][k.min(2) as usize]
+    }
+
+    #[test]
+    fn scales_recent_days_by_completeness() {
+        let nowcast = Nowcast::estimate(&cfg(), &history(0, steady), PLACE).unwrap();
+        for back in 0..3 {
+            let p = nowcast.get(day(LAST - back)).unwrap();
+            assert!((p.estimate - 100.0).abs() < 1e-9, "{back} days back: {p:?}");
+            assert!((p.lo - p.estimate).abs() < 1e-9 && (p.hi - p.estimate).abs() < 1e-9, "{p:?}");
+        }
+        assert!(nowcast.get(day(LAST - 3)).is_none());
+    }
+
+    #[test]
+    fn spread_in_completeness_widens_the_range() {
+        // Same-day completeness alternates between 40% and 60%
+        let uneven = |k: i64, d: i64| match k {
+            0 if d % 2 == 0 => 0.4,
+            0 => 0.6,
+            k => steady(k, d),
+        };
+        let nowcast = Nowcast::estimate(&cfg(), &history(0, uneven), PLACE).unwrap();
+        let p = nowcast.get(day(LAST)).unwrap();
+        let observed = 40.0;
+        assert!((p.estimate - observed / 0.5).abs() < 1.0, "{p:?}");
+        assert!((p.lo - observed / 0.6).abs() < 1e-9, "{p:?}");
+        assert!((p.hi - observed / 0.4).abs() < 1e-9, "{p:?}");
+    }
+
+    #[test]
+    fn needs_dates_old_enough_to_be_final() {
+        // Snapshots only from day 25: no date both final and seen early
+        assert!(Nowcast::estimate(&cfg(), &history(25, steady), PLACE).is_none());
+        assert!(Nowcast::estimate(&cfg(), &history(0, steady), "Beaver").is_none());
+    }
+
+    #[test]
+    fn adjusted_falls_back_to_observed() {
+        let nowcast = Nowcast::estimate(&cfg(), &history(0, steady), PLACE).unwrap();
+        assert_eq!(nowcast.adjusted(day(10), 97), (97.0, 97.0, 97.0));
+        assert_eq!(nowcast.adjusted(day(LAST), 50).0, nowcast.get(day(LAST)).unwrap().estimate);
+    }
+}
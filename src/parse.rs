@@ -0,0 +1,304 @@
+//! CSV loading with a per-file parse report.
+//!
+//! Rows that fail to deserialize used to vanish silently, which is how a
+//! renamed upstream column turns into an empty plot.  Every file read through
+//! `csvrecs` now leaves a `ParseReport` behind, and `print_reports` puts the
+//! interesting ones at the bottom of the daily output.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::Config;
+use crate::schema::{canonical_headers, Schema};
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseMode {
+    /// Any bad row fails the whole file
+    Strict,
+    /// Bad rows are dropped and counted
+    Lenient,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParseOptions {
+    pub mode: ParseMode,
+    /// How many row errors to keep per file
+    pub max_errors: usize,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            mode: ParseMode::Lenient,
+            max_errors: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub line: Option<u64>,
+    pub field: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseReport {
+    pub file: PathBuf,
+    pub rows_read: usize,
+    pub rows_dropped: usize,
+    /// The first `max_errors` errors, in file order
+    pub errors: Vec<RowError>,
+}
+
+impl std::fmt::Display for ParseReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} rows read, {} dropped",
+            self.file.display(),
+            self.rows_read,
+            self.rows_dropped
+        )?;
+        for e in &self.errors {
+            write!(f, "\n    line {}", e.line.map_or("?".to_string(), |l| l.to_string()))?;
+            if let Some(field) = &e.field {
+                write!(f, ", field {field:?}")?;
+            }
+            write!(f, ": {}", e.message)?;
+        }
+        if self.rows_dropped > self.errors.len() {
+            write!(f, "\n    ... and {} more", self.rows_dropped - self.errors.len())?;
+        }
+        Ok(())
+    }
+}
+
+impl ParseReport {
+    fn new(file: &Path) -> ParseReport {
+        ParseReport {
+            file: file.to_path_buf(),
+            rows_read: 0,
+            rows_dropped: 0,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Count one row, keeping the error if it failed.  `bad_date` gives the
+    /// column of the row's first unparseable date, for errors that don't say.
+    fn add_row<T>(
+        &mut self,
+        row: csv::Result<T>,
+        headers: &csv::StringRecord,
+        max_errors: usize,
+        bad_date: impl FnOnce() -> Option<usize>,
+    ) -> Option<T> {
+        self.rows_read += 1;
+        match row {
+            Ok(rec) => Some(rec),
+            Err(e) => {
+                self.rows_dropped += 1;
+                if self.errors.len() < max_errors {
+                    self.errors.push(row_error(&e, headers, bad_date));
+                }
+                None
+            }
+        }
+    }
+}
+
+static REPORTS: Mutex<Vec<ParseReport>> = Mutex::new(Vec::new());
+
+/// Read every row of `filename` as a `T`, keeping a report of what was dropped.
+///
+/// Headers are mapped through the configured aliases before deserializing;
+/// errors are reported against the file's own header names.
+pub fn read_csv<T>(cfg: &Config, filename: &Path) -> Result<(Vec<T>, ParseReport)>
+where
+    T: serde::de::DeserializeOwned + Schema,
+{
+    let infile = std::fs::File::open(filename)?;
+    read_from(cfg, infile, filename)
+}
+
+/// `read_csv` from any reader, reporting against `filename`.
+fn read_from<T, R>(cfg: &Config, input: R, filename: &Path) -> Result<(Vec<T>, ParseReport)>
+where
+    T: serde::de::DeserializeOwned + Schema,
+    R: std::io::Read,
+{
+    let mut rdr = csv::Reader::from_reader(input);
+    let headers = rdr.headers()?.clone();
+    let canonical = canonical_headers::<T>(cfg, &headers);
+    let mut report = ParseReport::new(filename);
+    let mut recs = Vec::new();
+    for row in rdr.records() {
+        let rec = match row {
+            Ok(row) => report.add_row(row.deserialize(Some(&canonical)), &headers, cfg.parse.max_errors, || {
+                bad_date::<T>(&row, &canonical)
+            }),
+            Err(e) => report.add_row::<T>(Err(e), &headers, cfg.parse.max_errors, || None),
+        };
+        recs.extend(rec);
+    }
+    Ok((recs, report))
+}
+
+/// Stream `filename` a row at a time instead of collecting it, for feeds too
+/// big to hold in memory.  `f` gets each raw row plus the (alias-mapped)
+/// headers, so it can deserialize into a type that borrows from the row; an
+/// error it returns counts the row as dropped.
+pub fn stream_csv<T, F>(cfg: &Config, filename: impl AsRef<Path>, mut f: F) -> Result<()>
+where
+    T: Schema,
+    F: FnMut(&csv::StringRecord, &csv::StringRecord) -> csv::Result<()>,
+{
+    let filename = filename.as_ref();
+    let infile = std::fs::File::open(filename)?;
+    let mut rdr = csv::Reader::from_reader(std::io::BufReader::new(infile));
+    let headers = rdr.headers()?.clone();
+    let canonical = canonical_headers::<T>(cfg, &headers);
+    let mut report = ParseReport::new(filename);
+    let mut row = csv::StringRecord::new();
+    loop {
+        match rdr.read_record(&mut row) {
+            Ok(true) => {
+                let res = f(&row, &canonical);
+                report.add_row(res, &headers, cfg.parse.max_errors, || bad_date::<T>(&row, &canonical));
+            }
+            Ok(false) => break,
+            Err(e) if e.is_io_error() => return Err(e.into()),
+            Err(e) => {
+                report.add_row::<()>(Err(e), &headers, cfg.parse.max_errors, || None);
+            }
+        }
+    }
+    finish(cfg, report)
+}
+
+/// Index of the first of `T`'s date columns in `row` that doesn't parse.
+fn bad_date<T: Schema>(row: &csv::StringRecord, canonical: &csv::StringRecord) -> Option<usize> {
+    canonical.iter().zip(row).position(|(header, value)| {
+        T::DATES
+            .iter()
+            .any(|(column, format)| *column == header && chrono::NaiveDate::parse_from_str(value, format).is_err())
+    })
+}
+
+fn row_error(e: &csv::Error, headers: &csv::StringRecord, bad_date: impl FnOnce() -> Option<usize>) -> RowError {
+    let line = e.position().map(|p| p.line());
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => RowError {
+            line,
+            field: err
+                .field()
+                .map(|i| i as usize)
+                .or_else(bad_date)
+                .and_then(|i| headers.get(i))
+                .map(str::to_string),
+            message: err.kind().to_string(),
+        },
+        _ => RowError {
+            line,
+            field: None,
+            message: e.to_string(),
+        },
+    }
+}
+
+/// `read_csv` with the configured options, filing the report for `print_reports`.
+pub fn csvrecs<T>(cfg: &Config, filename: impl AsRef<Path>) -> Result<Vec<T>>
+where
+    T: serde::de::DeserializeOwned + Schema,
+{
+    let (recs, report) = read_csv(cfg, filename.as_ref())?;
+    finish(cfg, report)?;
+    Ok(recs)
+}
+
+/// File `report` for `print_reports`.  In strict mode any dropped row fails
+/// the whole file, with the report as the error; in lenient mode the good
+/// rows are used regardless.
+fn finish(cfg: &Config, report: ParseReport) -> Result<()> {
+    let result = if cfg.parse.mode == ParseMode::Strict && report.rows_dropped > 0 {
+        Err(anyhow!("strict parse failed: {report}"))
+    } else {
+        Ok(())
+    };
+    record(report);
+    result
+}
+
+fn record(report: ParseReport) {
+    let mut reports = REPORTS.lock().unwrap();
+    // The same snapshot gets read once per jurisdiction; keep one report per file.
+    reports.retain(|r| r.file != report.file);
+    reports.push(report);
+}
+
+/// Print a feed health section covering every file read so far.
+pub fn print_reports() {
+    let reports = REPORTS.lock().unwrap();
+    println!("## Data feeds");
+    let bad: Vec<&ParseReport> = reports.iter().filter(|r| r.rows_dropped > 0).collect();
+    if bad.is_empty() {
+        println!(
+            "All {} files parsed cleanly ({} rows).",
+            reports.len(),
+            reports.iter().map(|r| r.rows_read).sum::<usize>()
+        );
+    }
+    for r in bad {
+        println!("WARNING {r}");
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(raw: &str) -> Vec<RowError> {
+        let (recs, report) =
+            read_from::<crate::CasesRecord, _>(&Config::default(), raw.as_bytes(), Path::new("cases.csv")).unwrap();
+        assert_eq!(recs.len() + report.rows_dropped, report.rows_read);
+        report.errors
+    }
+
+    #[test]
+    fn names_the_column_of_a_bad_number() {
+        let errs = errors("Jurisdiction,Date,New Cases\nAllegheny,01/02/2021,12\nAllegheny,01/03/2021,lots\n");
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].line, Some(3));
+        assert_eq!(errs[0].field.as_deref(), Some("New Cases"));
+    }
+
+    #[test]
+    fn names_the_column_of_a_bad_date() {
+        let errs = errors("Jurisdiction,Date,New Cases\nAllegheny,2021-01-02,12\n");
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].line, Some(2));
+        assert_eq!(errs[0].field.as_deref(), Some("Date"));
+        assert!(errs[0].message.contains("2021-01-02"), "{}", errs[0].message);
+    }
+
+    #[test]
+    fn names_an_aliased_date_column_as_the_file_has_it() {
+        let mut cfg = Config::default();
+        cfg.aliases.insert(
+            "cases".to_string(),
+            [("Date".to_string(), vec!["Report Date".to_string()])].into_iter().collect(),
+        );
+        let (_, report) = read_from::<crate::CasesRecord, _>(
+            &cfg,
+            "Jurisdiction,Report Date,New Cases\nAllegheny,soon,1\n".as_bytes(),
+            Path::new("cases.csv"),
+        )
+        .unwrap();
+        assert_eq!(report.errors[0].field.as_deref(), Some("Report Date"));
+    }
+}
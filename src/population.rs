@@ -0,0 +1,184 @@
+//! Resident populations for per-capita figures.
+//!
+//! Built in are the 2020 Census counts for Pennsylvania and its 67 counties,
+//! under the names the PA feeds use.  A CSV with `Jurisdiction,Population`
+//! columns named by `populations` in the config adds to or replaces entries,
+//! and a `population` set on a configured jurisdiction beats both.
+//!
+//! For age-specific rates there's also a table of Allegheny County residents
+//! per WPRDC age bucket, which `age_populations` in the config can replace.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::config::{Config, Jurisdiction};
+use crate::parse::csvrecs;
+use crate::schema::Schema;
+
+/// 2020 Census resident population (P1 redistricting counts).
+const PA_2020: &[(&str, u32)] = &[
+    ("Pennsylvania", 13002700),
+    ("Adams", 103852),
+    ("Allegheny", 1250578),
+    ("Armstrong", 65558),
+    ("Beaver", 168215),
+    ("Bedford", 47577),
+    ("Berks", 428849),
+    ("Blair", 122822),
+    ("Bradford", 59967),
+    ("Bucks", 646538),
+    ("Butler", 193763),
+    ("Cambria", 133472),
+    ("Cameron", 4547),
+    ("Carbon", 64749),
+    ("Centre", 158172),
+    ("Chester", 534413),
+    ("Clarion", 37241),
+    ("Clearfield", 80562),
+    ("Clinton", 37450),
+    ("Columbia", 64727),
+    ("Crawford", 83938),
+    ("Cumberland", 259469),
+    ("Dauphin", 286401),
+    ("Delaware", 576830),
+    ("Elk", 30990),
+    ("Erie", 270876),
+    ("Fayette", 128804),
+    ("Forest", 6973),
+    ("Franklin", 155932),
+    ("Fulton", 14556),
+    ("Greene", 35954),
+    ("Huntingdon", 44092),
+    ("Indiana", 83246),
+    ("Jefferson", 44492),
+    ("Juniata", 23509),
+    ("Lackawanna", 215896),
+    ("Lancaster", 552984),
+    ("Lawrence", 86070),
+    ("Lebanon", 143257),
+    ("Lehigh", 374557),
+    ("Luzerne", 325594),
+    ("Lycoming", 114188),
+    ("McKean", 40432),
+    ("Mercer", 110652),
+    ("Mifflin", 46143),
+    ("Monroe", 168327),
+    ("Montgomery", 856553),
+    ("Montour", 18136),
+    ("Northampton", 312951),
+    ("Northumberland", 91647),
+    ("Perry", 45842),
+    ("Philadelphia", 1603797),
+    ("Pike", 58535),
+    ("Potter", 16396),
+    ("Schuylkill", 143049),
+    ("Snyder", 39736),
+    ("Somerset", 74129),
+    ("Sullivan", 5840),
+    ("Susquehanna", 38434),
+    ("Tioga", 41045),
+    ("Union", 42681),
+    ("Venango", 50454),
+    ("Warren", 38587),
+    ("Washington", 209349),
+    ("Wayne", 51155),
+    ("Westmoreland", 354663),
+    ("Wyoming", 26069),
+    ("York", 456438),
+];
+
+/// Allegheny County residents per WPRDC age bucket: Census ACS 2019
+/// estimates, rounded to the nearest thousand.  Use `age_populations` for
+/// exact or newer figures.
+const ALLEGHENY_AGES_2019: &[(&str, u32)] = &[
+    ("0 to 9", 127000),
+    ("10 to 19", 137000),
+    ("20 to 29", 180000),
+    ("30 to 39", 162000),
+    ("40 to 49", 144000),
+    ("50 to 59", 173000),
+    ("60 to 69", 164000),
+    ("70+", 163000),
+];
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PopulationRecord {
+    #[serde(rename = "Jurisdiction")]
+    jurisdiction: String,
+    #[serde(rename = "Population")]
+    population: u32,
+}
+
+impl Schema for PopulationRecord {
+    const KIND: &'static str = "populations";
+    const COLUMNS: &'static [&'static str] = &["Jurisdiction", "Population"];
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct AgePopulationRecord {
+    #[serde(rename = "Age")]
+    age: String,
+    #[serde(rename = "Population")]
+    population: u32,
+}
+
+impl Schema for AgePopulationRecord {
+    const KIND: &'static str = "age-populations";
+    const COLUMNS: &'static [&'static str] = &["Age", "Population"];
+}
+
+pub struct Populations {
+    by_name: HashMap<String, u32>,
+}
+
+impl Populations {
+    /// The built-in table plus the configured override file, if any.
+    pub fn load(cfg: &Config) -> Result<Populations> {
+        let mut by_name: HashMap<String, u32> = PA_2020.iter().map(|(n, p)| (n.to_string(), *p)).collect();
+        if let Some(path) = &cfg.populations {
+            for r in csvrecs::<PopulationRecord>(cfg, path)? {
+                by_name.insert(r.jurisdiction, r.population);
+            }
+        }
+        Ok(Populations { by_name })
+    }
+
+    pub fn get(&self, jurisdiction: &str) -> Option<u32> {
+        self.by_name.get(jurisdiction).copied()
+    }
+
+    /// Population of a configured jurisdiction, preferring its own setting.
+    pub fn of(&self, j: &Jurisdiction) -> Option<u32> {
+        j.population.or_else(|| self.get(&j.name))
+    }
+}
+
+/// Residents per age bucket, keyed by the lowercased bucket name as in the
+/// test records.
+pub struct AgePopulations {
+    by_age: BTreeMap<String, u32>,
+}
+
+impl AgePopulations {
+    /// The built-in Allegheny table, or the configured file instead of it.
+    pub fn load(cfg: &Config) -> Result<AgePopulations> {
+        let by_age = match &cfg.age_populations {
+            Some(path) => csvrecs::<AgePopulationRecord>(cfg, path)?
+                .into_iter()
+                .map(|r| (r.age.to_lowercase(), r.population))
+                .collect(),
+            None => ALLEGHENY_AGES_2019.iter().map(|(a, p)| (a.to_string(), *p)).collect(),
+        };
+        Ok(AgePopulations { by_age })
+    }
+
+    pub fn get(&self, age: &str) -> Option<u32> {
+        self.by_age.get(&age.to_lowercase()).copied()
+    }
+}
+
+/// `count` per 100,000 residents.
+pub fn per_100k(count: f64, population: u32) -> f64 {
+    count * 100000.0 / population as f64
+}
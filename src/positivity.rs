@@ -0,0 +1,195 @@
+//! Test positivity from the WPRDC test records.
+//!
+//! Every test is counted, not just cases, so the share that came back
+//! positive can be tracked overall and per age bucket.  Tests are keyed both
+//! by report date, which is when they show up in the counts, and by
+//! collection date, which is when the infection was caught but fills in for
+//! days afterward.  Inconclusive results count toward neither side.
+
+use anyhow::Result;
+use plotters::prelude::*;
+
+use crate::testrecs::Tally;
+use crate::TestRecord;
+
+/// Key for the all-ages counts
+pub const ALL: &str = "all";
+
+#[derive(Debug, Default)]
+pub struct Positivity {
+    tests: Tally,
+    positives: Tally,
+}
+
+/// Positive or negative, or None for inconclusive and the like.
+fn outcome(rec: &TestRecord) -> Option<bool> {
+    if rec.test_result.eq_ignore_ascii_case("positive") {
+        Some(true)
+    } else if rec.test_result.eq_ignore_ascii_case("negative") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+impl Positivity {
+    pub fn add(&mut self, date: chrono::NaiveDate, rec: &TestRecord) {
+        let positive = match outcome(rec) {
+            Some(p) => p,
+            None => return,
+        };
+        let age = rec.age_bucket.to_lowercase();
+        for key in [ALL, &age] {
+            self.tests.add(date, key);
+            if positive {
+                self.positives.add(date, key);
+            }
+        }
+    }
+
+    pub fn date_range(&self) -> Option<(chrono::NaiveDate, chrono::NaiveDate)> {
+        self.tests.date_range()
+    }
+
+    /// Age buckets seen, in sorted order.
+    pub fn ages(&self) -> Vec<&str> {
+        self.tests.keys().into_iter().filter(|k| *k != ALL).collect()
+    }
+
+    /// Percent positive for `key` over each `days`-day window ending on each
+    /// date from `from` through `to`.  None for windows with no tests.
+    pub fn rolling(&self, key: &str, days: usize, from: chrono::NaiveDate, to: chrono::NaiveDate) -> Vec<Option<f64>> {
+        let lead = from - chrono::Duration::days(days as i64 - 1);
+        let tests = self.tests.series(key, lead, to);
+        let positives = self.positives.series(key, lead, to);
+        tests
+            .windows(days)
+            .zip(positives.windows(days))
+            .map(|(t, p)| {
+                let t: u32 = t.iter().sum();
+                let p: u32 = p.iter().sum();
+                (t > 0).then(|| p as f64 * 100.0 / t as f64)
+            })
+            .collect()
+    }
+
+    /// Percent positive for `key` over the 7 days ending `to`.
+    pub fn week(&self, key: &str, to: chrono::NaiveDate) -> Option<f64> {
+        *self.rolling(key, 7, to, to).first()?
+    }
+}
+
+/// Positivity keyed both ways.
+#[derive(Debug, Default)]
+pub struct TestPositivity {
+    pub by_report: Positivity,
+    pub by_collection: Positivity,
+}
+
+impl TestPositivity {
+    pub fn add(&mut self, rec: &TestRecord) {
+        self.by_report.add(rec.report_date, rec);
+        self.by_collection.add(rec.collection_date, rec);
+    }
+
+    /// The text report: the last day's and last week's positivity, overall
+    /// and by age, by report date and by collection date.
+    pub fn print(&self) {
+        for (what, p) in [("report", &self.by_report), ("collection", &self.by_collection)] {
+            let (_, last) = match p.date_range() {
+                Some(r) => r,
+                None => continue,
+            };
+            let pct = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.1}%", v));
+            println!(
+                "Test positivity by {what} date, to {last}: {} that day, {} over 7 days  ",
+                pct(*p.rolling(ALL, 1, last, last).first().unwrap_or(&None)),
+                pct(p.week(ALL, last))
+            );
+            let ages = p
+                .ages()
+                .iter()
+                .map(|age| format!("{age} {}", pct(p.week(age, last))))
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("7 day positivity by age: {ages}  ");
+        }
+    }
+}
+
+/// 7 day positivity by report and by collection date, overall, and by age
+/// by report date.
+pub fn plot_positivity(positivity: &TestPositivity) -> Result<()> {
+    let (min_date, last_date) = positivity
+        .by_report
+        .date_range()
+        .ok_or_else(|| anyhow::anyhow!("no tests to plot"))?;
+    let min_date = min_date + chrono::Duration::days(6);
+    let max_date = last_date + chrono::Duration::days(1);
+    let root = BitMapBackend::new("test_positivity.png", (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let ages = positivity.by_report.ages();
+    let mut lines: Vec<(String, Vec<Option<f64>>, ShapeStyle)> = ages
+        .iter()
+        .enumerate()
+        .map(|(i, age)| {
+            let style = plotters::style::ShapeStyle {
+                color: plotters::style::Palette99::pick(i + 2).mix(0.6).to_rgba(),
+                filled: true,
+                stroke_width: 1,
+            };
+            (age.to_string(), positivity.by_report.rolling(age, 7, min_date, last_date), style)
+        })
+        .collect();
+    lines.push((
+        "All ages, by collection date".to_string(),
+        positivity.by_collection.rolling(ALL, 7, min_date, last_date),
+        plotters::style::ShapeStyle {
+            color: plotters::style::Palette99::pick(1).mix(0.9).to_rgba(),
+            filled: true,
+            stroke_width: 2,
+        },
+    ));
+    lines.push((
+        "All ages, by report date".to_string(),
+        positivity.by_report.rolling(ALL, 7, min_date, last_date),
+        plotters::style::ShapeStyle {
+            color: plotters::style::Palette99::pick(0).mix(0.9).to_rgba(),
+            filled: true,
+            stroke_width: 3,
+        },
+    ));
+    let max_y = lines
+        .iter()
+        .flat_map(|(_, vals, _)| vals.iter().flatten())
+        .fold(10.0, |a: f64, b| a.max(*b))
+        * 1.05;
+
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption("7 day test positivity: Allegheny County", ("sans-serif", 40))
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .x_labels(9)
+        .x_desc("Date")
+        .y_desc("% positive")
+        .draw()?;
+    for (label, vals, style) in lines {
+        let points = (0..).map(|d| min_date + chrono::Duration::days(d)).zip(vals);
+        chart
+            .draw_series(LineSeries::new(points.filter_map(|(d, v)| Some((d, v?))), style.clone()))?
+            .label(label)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
+    }
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
@@ -0,0 +1,365 @@
+//! How many people are in the hospital per case, lagged.
+//!
+//! Hospital and ICU census trail cases by a week or two.  Dividing today's
+//! census by the 7 day average of cases that many days back gives a rough
+//! severity figure that can be held up against earlier waves.  The lag is
+//! configured, or else picked as the one where census and lagged cases
+//! track each other best.
+
+use anyhow::Result;
+use plotters::prelude::*;
+use serde::Deserialize;
+
+use crate::config::Config;
+use crate::gaps;
+use crate::nowcast::Nowcast;
+use crate::HospitalRecord;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RatioOptions {
+    pub enabled: bool,
+    /// Days cases lead the census by; estimated per jurisdiction if unset
+    pub lag_days: Option<usize>,
+    /// Longest lag tried when estimating
+    pub max_lag_days: usize,
+    /// A wave peak is the highest 7 day average within this many days
+    /// either side
+    pub peak_window_days: usize,
+}
+
+impl Default for RatioOptions {
+    fn default() -> Self {
+        RatioOptions {
+            enabled: true,
+            lag_days: None,
+            max_lag_days: 28,
+            peak_window_days: 60,
+        }
+    }
+}
+
+/// Ratios are left out when the lagged 7 day average is below this, where
+/// they're mostly noise.
+const MIN_CASES: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RatioPoint {
+    pub date: chrono::NaiveDate,
+    pub hospitalized: Option<f64>,
+    pub icu: Option<f64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseRatios {
+    pub lag: usize,
+    /// Whether `lag` was estimated rather than configured
+    pub estimated: bool,
+    pub points: Vec<RatioPoint>,
+    /// Dates of the 7 day average case peaks of past waves
+    pub peaks: Vec<chrono::NaiveDate>,
+}
+
+fn correlation(pairs: &[(f64, f64)]) -> Option<f64> {
+    let n = pairs.len() as f64;
+    if pairs.len() < 3 {
+        return None;
+    }
+    let (mx, my) = (
+        pairs.iter().map(|(x, _)| x).sum::<f64>() / n,
+        pairs.iter().map(|(_, y)| y).sum::<f64>() / n,
+    );
+    let sxy: f64 = pairs.iter().map(|(x, y)| (x - mx) * (y - my)).sum();
+    let sxx: f64 = pairs.iter().map(|(x, _)| (x - mx).powi(2)).sum();
+    let syy: f64 = pairs.iter().map(|(_, y)| (y - my).powi(2)).sum();
+    (sxx > 0.0 && syy > 0.0).then(|| sxy / (sxx * syy).sqrt())
+}
+
+impl CaseRatios {
+    /// Ratios for one jurisdiction's date-sorted records.  The last record's
+    /// cases are incomplete and left out, and recent days use the nowcast if
+    /// there is one.  None if there's too little data to estimate a lag.
+    pub fn compute(cfg: &Config, recs: &[HospitalRecord], nowcast: Option<&Nowcast>) -> Option<CaseRatios> {
+        let opts = &cfg.ratio;
+        let last = recs.len().checked_sub(1)?;
+        let cases: Vec<f64> = recs
+            .iter()
+            .map(|r| {
+                let observed = r.new_cases.unwrap_or(0);
+                nowcast.map_or(observed as f64, |nc| nc.adjusted(r.date, observed).0)
+            })
+            .collect();
+        // cases7[i] is the 7 day average ending on recs[i]
+        let cases7: Vec<Option<f64>> = (0..recs.len())
+            .map(|i| (i >= 6 && i < last).then(|| cases[i - 6..=i].iter().sum::<f64>() / 7.0))
+            .collect();
+        let census = |vals: Vec<gaps::Point>| -> Vec<Option<f64>> {
+            vals.iter().map(|p| p.value.map(|v| v as f64)).collect()
+        };
+        let hosp = census(gaps::fill(cfg.gap_policy, recs.iter().map(|r| r.covid_hospitalized)));
+        let icu = census(gaps::fill(cfg.gap_policy, recs.iter().map(|r| r.covid_icu)));
+
+        let lagged_pairs = |lag: usize| -> Vec<(f64, f64)> {
+            (lag..recs.len())
+                .filter_map(|i| Some((cases7[i - lag]?, hosp[i]?)))
+                .collect()
+        };
+        let (lag, estimated) = match opts.lag_days {
+            Some(lag) => (lag, false),
+            None => {
+                let best = (0..=opts.max_lag_days)
+                    .filter_map(|lag| Some((lag, correlation(&lagged_pairs(lag))?)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))?;
+                (best.0, true)
+            }
+        };
+
+        let ratio = |num: Option<f64>, i: usize| -> Option<f64> {
+            let c = cases7[i.checked_sub(lag)?]?;
+            (c >= MIN_CASES).then_some(num? / c)
+        };
+        let points = (0..recs.len())
+            .map(|i| RatioPoint {
+                date: recs[i].date,
+                hospitalized: ratio(hosp[i], i),
+                icu: ratio(icu[i], i),
+            })
+            .collect();
+
+        let w = opts.peak_window_days;
+        let overall_max = cases7.iter().flatten().fold(0.0, |a: f64, b| a.max(*b));
+        let peaks = (w..recs.len().saturating_sub(w))
+            .filter(|&i| {
+                let c = match cases7[i] {
+                    Some(c) => c,
+                    None => return false,
+                };
+                // Small bumps aren't waves
+                c >= overall_max / 4.0
+                    && cases7[i - w..=i + w]
+                        .iter()
+                        .enumerate()
+                        .all(|(j, o)| o.is_none_or(|o| o < c || (o == c && j >= w)))
+            })
+            .map(|i| recs[i].date)
+            .collect();
+        Some(CaseRatios {
+            lag,
+            estimated,
+            points,
+            peaks,
+        })
+    }
+
+    fn at(&self, date: chrono::NaiveDate) -> Option<&RatioPoint> {
+        self.points.iter().find(|p| p.date == date)
+    }
+
+    /// Latest ratios, then the ratios `lag` days after each past wave's peak.
+    pub fn print(&self) {
+        let fmt = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.2}", v));
+        let current = match self.points.iter().rev().find(|p| p.hospitalized.is_some() || p.icu.is_some()) {
+            Some(p) => p,
+            None => return,
+        };
+        println!(
+            "Hospitalized per daily case {} days earlier (7 day avg, lag {}): {} now, {} in ICU  ",
+            self.lag,
+            if self.estimated { "estimated" } else { "configured" },
+            fmt(current.hospitalized),
+            fmt(current.icu)
+        );
+        let lag = chrono::Duration::days(self.lag as i64);
+        let waves: Vec<String> = self
+            .peaks
+            .iter()
+            .filter_map(|peak| {
+                let p = self.at(*peak + lag)?;
+                Some(format!("{} peak {} ({} in ICU)", peak.format("%b %Y"), fmt(p.hospitalized), fmt(p.icu)))
+            })
+            .collect();
+        if !waves.is_empty() {
+            println!("At past wave peaks: {}  ", waves.join(", "));
+        }
+    }
+}
+
+/// Hospital and ICU ratios since October 2020, with the wave peaks marked.
+pub fn plot_ratios(ratios: &CaseRatios, jurisdiction: &str) -> Result<()> {
+    let mut img_path = std::path::PathBuf::from(format!("{}_case_ratio", str::replace(jurisdiction, " ", "_")));
+    img_path.set_extension("png");
+    let min_date = chrono::NaiveDate::from_ymd(2020, 10, 1);
+    let points: Vec<&RatioPoint> = ratios.points.iter().filter(|p| p.date >= min_date).collect();
+    let max_date = match points.last() {
+        Some(p) => p.date + chrono::Duration::days(1),
+        None => return Err(anyhow::anyhow!("no ratios to plot")),
+    };
+    let mut max_y = points
+        .iter()
+        .filter_map(|p| p.hospitalized)
+        .fold(0.1, f64::max);
+    max_y += max_y / 20.0;
+
+    let root = BitMapBackend::new(&img_path, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let hosp_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(0).mix(0.9).to_rgba(),
+        filled: true,
+        stroke_width: 2,
+    };
+    let icu_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(5).mix(0.9).to_rgba(),
+        filled: true,
+        stroke_width: 2,
+    };
+    let peak_style = plotters::style::ShapeStyle {
+        color: BLACK.mix(0.4).to_rgba(),
+        filled: true,
+        stroke_width: 1,
+    };
+
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption(
+            format!("Census per case {} days earlier: {jurisdiction}", ratios.lag),
+            ("sans-serif", 40),
+        )
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .bold_line_style(BLACK.mix(0.10))
+        .light_line_style(BLACK.mix(0.05))
+        .x_labels(10)
+        .x_desc("Date")
+        .draw()?;
+    for (label, style, get) in [
+        ("Hospitalized per 7 day avg case", &hosp_style, (|p: &RatioPoint| p.hospitalized) as fn(&RatioPoint) -> Option<f64>),
+        ("ICU per 7 day avg case", &icu_style, |p: &RatioPoint| p.icu),
+    ] {
+        chart
+            .draw_series(LineSeries::new(
+                points.iter().filter_map(|p| Some((p.date, get(p)?.min(max_y)))),
+                style.clone(),
+            ))?
+            .label(label)
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
+    }
+    let lag = chrono::Duration::days(ratios.lag as i64);
+    let mut peaks = ratios.peaks.iter().map(|d| *d + lag).filter(|d| *d >= min_date).peekable();
+    if peaks.peek().is_some() {
+        chart
+            .draw_series(peaks.map(|d| PathElement::new(vec![(d, 0.0), (d, max_y)], peak_style.clone())))?
+            .label("Wave peak (lagged)")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], peak_style.clone()));
+    }
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: usize) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd(2021, 1, 1) + chrono::Duration::days(n as i64)
+    }
+
+    fn recs(cases: &[f64], hospitalized: &[Option<f64>]) -> Vec<HospitalRecord> {
+        cases
+            .iter()
+            .zip(hospitalized)
+            .enumerate()
+            .map(|(i, (c, h))| HospitalRecord {
+                county: "Allegheny".to_string(),
+                date: day(i),
+                adult_icu_beds_available: None,
+                adult_icu_beds_total: None,
+                med_surg_available: None,
+                med_surg_total: None,
+                covid_hospitalized: h.map(|h| h.round() as u32),
+                covid_ventilator: None,
+                covid_icu: h.map(|h| (h / 4.0).round() as u32),
+                new_cases: Some(c.round() as u32),
+            })
+            .collect()
+    }
+
+    /// Trailing 7 day average, as `compute` takes it.
+    fn avg7(cases: &[f64], i: usize) -> Option<f64> {
+        (i >= 6).then(|| cases[i - 6..=i].iter().sum::<f64>() / 7.0)
+    }
+
+    /// A slow wave, with census at 0.3 per case `lag` days before.
+    fn waves(lag: usize) -> (Vec<f64>, Vec<Option<f64>>) {
+        let cases: Vec<f64> = (0..300)
+            .map(|t| 1000.0 + 800.0 * (t as f64 * std::f64::consts::TAU / 90.0).sin())
+            .collect();
+        let hosp = (0..cases.len())
+            .map(|i| avg7(&cases, i.checked_sub(lag)?).map(|c| 0.3 * c))
+            .collect();
+        (cases, hosp)
+    }
+
+    #[test]
+    fn estimates_a_known_lag() {
+        let (cases, hosp) = waves(12);
+        let ratios = CaseRatios::compute(&Config::default(), &recs(&cases, &hosp), None).unwrap();
+        assert_eq!((ratios.lag, ratios.estimated), (12, true));
+        let p = ratios.points[250];
+        assert!((p.hospitalized.unwrap() - 0.3).abs() < 0.01, "{p:?}");
+        assert!((p.icu.unwrap() - 0.075).abs() < 0.01, "{p:?}");
+    }
+
+    #[test]
+    fn configured_lag_is_used_as_is() {
+        let (cases, hosp) = waves(12);
+        let mut cfg = Config::default();
+        cfg.ratio.lag_days = Some(5);
+        let ratios = CaseRatios::compute(&cfg, &recs(&cases, &hosp), None).unwrap();
+        assert_eq!((ratios.lag, ratios.estimated), (5, false));
+    }
+
+    #[test]
+    fn leaves_out_the_last_day_and_small_counts() {
+        let (mut cases, hosp) = waves(0);
+        cases[200..220].iter_mut().for_each(|c| *c = 0.0);
+        let mut cfg = Config::default();
+        cfg.ratio.lag_days = Some(0);
+        let ratios = CaseRatios::compute(&cfg, &recs(&cases, &hosp), None).unwrap();
+        assert!(ratios.points[..6].iter().all(|p| p.hospitalized.is_none()));
+        assert!(ratios.points[213].hospitalized.is_none());
+        assert!(ratios.points[298].hospitalized.is_some());
+        assert!(ratios.points[299].hospitalized.is_none());
+    }
+
+    #[test]
+    fn finds_wave_peaks_but_not_bumps() {
+        let bump = |t: f64, at: f64, height: f64, width: f64| height * (-((t - at) / width).powi(2)).exp();
+        let cases: Vec<f64> = (0..520)
+            .map(|t| {
+                let t = t as f64;
+                10.0 + bump(t, 100.0, 2000.0, 15.0) + bump(t, 250.0, 1000.0, 15.0) + bump(t, 400.0, 200.0, 10.0)
+            })
+            .collect();
+        let hosp = vec![Some(100.0); cases.len()];
+        let mut cfg = Config::default();
+        cfg.ratio.lag_days = Some(7);
+        let ratios = CaseRatios::compute(&cfg, &recs(&cases, &hosp), None).unwrap();
+        // A trailing average peaks 3 days after the daily counts do
+        assert_eq!(ratios.peaks, [day(103), day(253)]);
+    }
+
+    #[test]
+    fn too_little_data() {
+        assert!(CaseRatios::compute(&Config::default(), &[], None).is_none());
+        let (cases, hosp) = waves(12);
+        assert!(CaseRatios::compute(&Config::default(), &recs(&cases[..8], &hosp[..8]), None).is_none());
+    }
+}
@@ -0,0 +1,349 @@
+//! How long tests take to be reported after they're collected.
+//!
+//! Every test record has a collection date and a report date; the days in
+//! between are the reporting lag.  Its distribution is summed up for each
+//! week of report dates, overall and per case status.  The file has no test
+//! type, but case status is close to one: confirmed cases are molecular
+//! (PCR) positives, probable cases are mostly antigen positives, and the
+//! rest are negatives of either kind.
+
+use anyhow::{anyhow, Result};
+use plotters::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::table::{Cell, Format, Table};
+use crate::testrecs::summarize_tests;
+use crate::TestRecord;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportLagOptions {
+    /// Weeks before the latest that make up the usual lag
+    pub baseline_weeks: usize,
+    /// Warn when the latest week's 90th percentile lag is this many times
+    /// the usual one
+    pub warn_factor: f64,
+}
+
+impl Default for ReportLagOptions {
+    fn default() -> Self {
+        ReportLagOptions {
+            baseline_weeks: 8,
+            warn_factor: 1.5,
+        }
+    }
+}
+
+/// Key for the counts over every case status
+pub const ALL: &str = "All tests";
+
+/// A longer 90th percentile lag isn't worth a warning unless it's at least
+/// this many days longer, whatever the factor says.
+const MIN_EXTRA_DAYS: u32 = 2;
+
+/// Longer lags are taken for bad collection dates and left out, so one
+/// typo'd year can't blow up the histograms.
+const MAX_LAG_DAYS: usize = 365;
+
+/// Lag distribution of the tests reported in one week.
+#[derive(Debug, Clone, Copy)]
+pub struct LagStats {
+    /// Last report date of the week (or other span)
+    pub week_to: chrono::NaiveDate,
+    pub tests: u32,
+    pub median: u32,
+    pub p90: u32,
+    pub max: u32,
+}
+
+/// Histograms of lag in days, per report date and case status.
+#[derive(Debug, Default)]
+pub struct ReportLags {
+    days: BTreeMap<chrono::NaiveDate, HashMap<String, Vec<u32>>>,
+    /// Records left out for a negative lag or one over `MAX_LAG_DAYS`
+    pub outliers: u32,
+}
+
+fn quantile(hist: &[u32], total: u32, q: f64) -> u32 {
+    let target = (total as f64 * q).ceil().max(1.0) as u32;
+    let mut seen = 0;
+    for (lag, n) in hist.iter().enumerate() {
+        seen += n;
+        if seen >= target {
+            return lag as u32;
+        }
+    }
+    hist.len().saturating_sub(1) as u32
+}
+
+impl ReportLags {
+    /// Records reported before they were collected, or more than a year
+    /// after, are data entry errors; they're only counted.
+    pub fn add(&mut self, rec: &TestRecord) {
+        let lag = match usize::try_from((rec.report_date - rec.collection_date).num_days()) {
+            Ok(lag) if lag <= MAX_LAG_DAYS => lag,
+            _ => {
+                self.outliers += 1;
+                return;
+            }
+        };
+        let day = self.days.entry(rec.report_date).or_default();
+        for key in [ALL, rec.case_status] {
+            let hist = day.entry(key.to_string()).or_default();
+            if hist.len() <= lag {
+                hist.resize(lag + 1, 0);
+            }
+            hist[lag] += 1;
+        }
+    }
+
+    /// The latest report date.
+    pub fn last_date(&self) -> Option<chrono::NaiveDate> {
+        self.days.keys().next_back().copied()
+    }
+
+    /// All tests first, then each case status in sorted order.
+    pub fn kinds(&self) -> Vec<&str> {
+        let mut kinds: Vec<&str> = self
+            .days
+            .values()
+            .flat_map(|day| day.keys().map(String::as_str))
+            .filter(|k| *k != ALL)
+            .collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+        kinds.insert(0, ALL);
+        kinds
+    }
+
+    /// The distribution for `kind` over the 7 report dates ending `week_to`,
+    /// or None if nothing was reported.
+    pub fn week(&self, kind: &str, week_to: chrono::NaiveDate) -> Option<LagStats> {
+        self.stats(&[kind], week_to - chrono::Duration::days(6), week_to)
+    }
+
+    /// The distribution for the given kinds together over report dates
+    /// `from` through `to`.
+    pub fn stats(&self, kinds: &[&str], from: chrono::NaiveDate, to: chrono::NaiveDate) -> Option<LagStats> {
+        let mut hist: Vec<u32> = Vec::new();
+        for h in self.days.range(from..=to).flat_map(|(_, day)| kinds.iter().filter_map(|k| day.get(*k))) {
+            if hist.len() < h.len() {
+                hist.resize(h.len(), 0);
+            }
+            hist.iter_mut().zip(h).for_each(|(a, b)| *a += b);
+        }
+        let tests: u32 = hist.iter().sum();
+        (tests > 0).then(|| LagStats {
+            week_to: to,
+            tests,
+            median: quantile(&hist, tests, 0.5),
+            p90: quantile(&hist, tests, 0.9),
+            max: hist.iter().rposition(|n| *n > 0).unwrap_or(0) as u32,
+        })
+    }
+
+    /// Every full week for `kind`, oldest first, with the last ending on the
+    /// latest report date.
+    pub fn weeks(&self, kind: &str) -> Vec<LagStats> {
+        let (first, last) = match (self.days.keys().next(), self.days.keys().next_back()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Vec::new(),
+        };
+        let mut weeks: Vec<LagStats> = (0..)
+            .map(|w| last - chrono::Duration::weeks(w))
+            .take_while(|d| *d - chrono::Duration::days(6) >= first)
+            .filter_map(|d| self.week(kind, d))
+            .collect();
+        weeks.reverse();
+        weeks
+    }
+
+    /// A warning line when the latest week's 90th percentile lag is well past
+    /// the median of the weeks before it.
+    pub fn print_warning(&self, opts: &ReportLagOptions) {
+        let weeks = self.weeks(ALL);
+        let (latest, before) = match weeks.split_last() {
+            Some(split) => split,
+            None => return,
+        };
+        let mut usual: Vec<u32> = before.iter().rev().take(opts.baseline_weeks).map(|w| w.p90).collect();
+        if usual.is_empty() {
+            return;
+        }
+        usual.sort_unstable();
+        let usual = usual[usual.len() / 2];
+        if latest.p90 as f64 > usual as f64 * opts.warn_factor && latest.p90 >= usual + MIN_EXTRA_DAYS {
+            println!(
+                "WARNING reporting lags are long: 90% of tests reported in the 7 days to {} took up to {} days \
+                 from collection (median {}), against a usual {} days over the {} weeks before  ",
+                latest.week_to,
+                latest.p90,
+                latest.median,
+                usual,
+                before.len().min(opts.baseline_weeks)
+            );
+        }
+    }
+}
+
+/// Weekly median and 90th percentile lag per case status, to `report_lag.png`.
+pub fn plot(lags: &ReportLags) -> Result<()> {
+    let series: Vec<(&str, Vec<LagStats>)> = lags.kinds().into_iter().map(|k| (k, lags.weeks(k))).collect();
+    let weeks = &series.first().ok_or_else(|| anyhow!("no tests to plot"))?.1;
+    let (min_date, max_date) = match (weeks.first(), weeks.last()) {
+        (Some(first), Some(last)) => (first.week_to, last.week_to + chrono::Duration::days(1)),
+        _ => return Err(anyhow!("no full weeks of tests to plot")),
+    };
+    let max_y = series
+        .iter()
+        .flat_map(|(_, weeks)| weeks.iter().map(|w| w.p90))
+        .fold(5, u32::max) as f64
+        * 1.05;
+
+    let root = BitMapBackend::new("report_lag.png", (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption("Days from collection to report: Allegheny County", ("sans-serif", 40))
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .x_labels(9)
+        .x_desc("Week reported")
+        .y_desc("Days")
+        .draw()?;
+    for (color, (kind, weeks)) in series.iter().enumerate() {
+        for (what, mix, get) in [
+            ("median", 0.9, (|w: &LagStats| w.median) as fn(&LagStats) -> u32),
+            ("90th percentile", 0.4, |w: &LagStats| w.p90),
+        ] {
+            let style = plotters::style::ShapeStyle {
+                color: plotters::style::Palette99::pick(color).mix(mix).to_rgba(),
+                filled: true,
+                stroke_width: if color == 0 { 3 } else { 1 },
+            };
+            chart
+                .draw_series(LineSeries::new(weeks.iter().map(|w| (w.week_to, get(w) as f64)), style.clone()))?
+                .label(format!("{kind}, {what}"))
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
+        }
+    }
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct LagsOpt {
+    #[structopt(long, default_value = "2021-01-01", help = "First report date to include (%Y-%m-%d)")]
+    since: String,
+    #[structopt(long, default_value = "text", help = "Table format: text, csv or json")]
+    format: Format,
+}
+
+/// Print the weekly lag distributions and plot them.
+pub fn lags(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &LagsOpt) -> Result<()> {
+    let since = chrono::NaiveDate::parse_from_str(&opt.since, "%Y-%m-%d")?;
+    let summary = summarize_tests(cfg, today, since)?;
+    let lags = &summary.lags;
+    let mut table = Table::new(
+        "Days from collection to report, by week reported",
+        &["Week to", "Case status", "Tests", "Median", "90th pct", "Max"],
+    );
+    for w in lags.weeks(ALL) {
+        for kind in lags.kinds() {
+            if let Some(k) = lags.week(kind, w.week_to) {
+                table.row(vec![
+                    Cell::text(w.week_to.to_string()),
+                    Cell::text(kind),
+                    k.tests.into(),
+                    k.median.into(),
+                    k.p90.into(),
+                    k.max.into(),
+                ]);
+            }
+        }
+    }
+    table.print(opt.format)?;
+    // Keep CSV and JSON output parseable
+    if opt.format == Format::Text {
+        if lags.outliers > 0 {
+            println!(
+                "Left out {} tests reported before collection or over {MAX_LAG_DAYS} days after  ",
+                lags.outliers
+            );
+        }
+        lags.print_warning(&cfg.report_lag);
+    }
+    plot(lags)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(n: i64) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd(2022, 3, 1) + chrono::Duration::days(n)
+    }
+
+    /// A test collected on `collected` and reported on `reported`.
+    fn rec(collected: chrono::NaiveDate, reported: chrono::NaiveDate, status: &str) -> TestRecord<'_> {
+        TestRecord {
+            indv_id: "1",
+            collection_date: collected,
+            report_date: reported,
+            test_result: "POSITIVE",
+            case_status: status,
+            hospital_flag: "",
+            icu_flag: "",
+            vent_flag: "",
+            age_bucket: "30 to 39",
+            sex: "F",
+            race: "",
+            ethnicity: "",
+            update_date: reported,
+        }
+    }
+
+    #[test]
+    fn lags_up_to_a_year_are_counted() {
+        let mut lags = ReportLags::default();
+        lags.add(&rec(day(-(MAX_LAG_DAYS as i64)), day(0), "Confirmed"));
+        assert_eq!(lags.outliers, 0);
+        assert_eq!(lags.week(ALL, day(0)).unwrap().max, MAX_LAG_DAYS as u32);
+    }
+
+    #[test]
+    fn long_and_negative_lags_are_outliers() {
+        let mut lags = ReportLags::default();
+        lags.add(&rec(day(-400), day(0), "Confirmed"));
+        lags.add(&rec(day(1), day(0), "Probable"));
+        assert_eq!(lags.outliers, 2);
+        assert!(lags.week(ALL, day(0)).is_none());
+        assert_eq!(lags.last_date(), None);
+    }
+
+    #[test]
+    fn weekly_quantiles_per_status() {
+        let mut lags = ReportLags::default();
+        for (lag, status) in [(0, "Confirmed"), (1, "Confirmed"), (2, "Probable"), (2, "Confirmed"), (9, "Probable")] {
+            lags.add(&rec(day(6 - lag), day(6), status));
+        }
+        lags.add(&rec(day(-400), day(6), "Confirmed"));
+        assert_eq!(lags.kinds(), [ALL, "Confirmed", "Probable"]);
+        let all = lags.week(ALL, day(6)).unwrap();
+        assert_eq!((all.tests, all.median, all.p90, all.max), (5, 2, 9, 9));
+        let confirmed = lags.week("Confirmed", day(6)).unwrap();
+        assert_eq!((confirmed.tests, confirmed.median, confirmed.max), (3, 1, 2));
+        assert_eq!(lags.outliers, 1);
+    }
+}
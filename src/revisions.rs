@@ -0,0 +1,234 @@
+//! `covidreport revisions`: what changed between two snapshots of the feeds.
+//!
+//! `count_case_delta` only compares grand totals; this breaks the difference
+//! down by jurisdiction and data date, separating brand new dates from
+//! revisions to dates that were already reported.  A date the newer vintage
+//! no longer has at all counts as revised to nothing.
+
+use anyhow::Result;
+use plotters::prelude::*;
+use std::collections::{BTreeMap, BTreeSet};
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::store::Store;
+use crate::{day_of, load_cases, load_hospitals, vintage_date};
+
+#[derive(Debug, StructOpt)]
+pub struct RevisionsOpt {
+    #[structopt(long, help = "Older vintage (%Y-%m-%d, default: the day before --to)")]
+    from: Option<String>,
+    #[structopt(long, help = "Newer vintage (%Y-%m-%d, default: --date or today)")]
+    to: Option<String>,
+    #[structopt(long, help = "Only this jurisdiction (default: the configured ones)")]
+    jurisdiction: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Metric {
+    Cases,
+    Hospitalized,
+    Icu,
+}
+
+impl Metric {
+    fn name(self) -> &'static str {
+        match self {
+            Metric::Cases => "cases",
+            Metric::Hospitalized => "hospitalized",
+            Metric::Icu => "ICU",
+        }
+    }
+}
+
+/// One metric's value for one jurisdiction and date, per vintage.
+type Values = BTreeMap<(Metric, String, chrono::NaiveDate), Option<u32>>;
+
+fn vintage_values(cfg: &Config, store: Option<&Store>, day: &chrono::DateTime<chrono::Local>) -> Result<Values> {
+    let mut values = Values::new();
+    for r in load_cases(cfg, store, day)? {
+        values.insert((Metric::Cases, r.county, r.date), r.new_cases);
+    }
+    for r in load_hospitals(cfg, store, day)? {
+        values.insert((Metric::Hospitalized, r.county.clone(), r.date), r.covid_hospitalized);
+        values.insert((Metric::Icu, r.county, r.date), r.covid_icu);
+    }
+    Ok(values)
+}
+
+#[derive(Debug)]
+struct Revision {
+    metric: Metric,
+    jurisdiction: String,
+    date: chrono::NaiveDate,
+    old: Option<u32>,
+    new: Option<u32>,
+    /// The date is gone from the newer vintage altogether
+    removed: bool,
+}
+
+impl Revision {
+    fn delta(&self) -> i64 {
+        self.new.unwrap_or(0) as i64 - self.old.unwrap_or(0) as i64
+    }
+}
+
+fn parse_day(s: &str) -> Result<chrono::DateTime<chrono::Local>> {
+    Ok(day_of(chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")?))
+}
+
+pub fn revisions(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &RevisionsOpt) -> Result<()> {
+    let to = match &opt.to {
+        Some(s) => parse_day(s)?,
+        None => *today,
+    };
+    let from = match &opt.from {
+        Some(s) => parse_day(s)?,
+        None => to - chrono::Duration::days(1),
+    };
+    let jurisdictions: BTreeSet<String> = match &opt.jurisdiction {
+        Some(j) => [j.clone()].into_iter().collect(),
+        None => cfg.jurisdictions.iter().map(|j| j.name.clone()).collect(),
+    };
+    let store = Store::open_existing(cfg)?;
+    let old = vintage_values(cfg, store.as_ref(), &from)?;
+    let new = vintage_values(cfg, store.as_ref(), &to)?;
+    let to_date = vintage_date(&to);
+
+    // Dates that are in the old vintage and changed, or are gone from the
+    // new one, are revisions; dates that only appear in the new one are just
+    // new data.
+    let mut revisions = Vec::new();
+    let mut added: BTreeMap<(Metric, &str), (usize, i64)> = BTreeMap::new();
+    for ((metric, jurisdiction, date), value) in &new {
+        if !jurisdictions.contains(jurisdiction) {
+            continue;
+        }
+        match old.get(&(*metric, jurisdiction.clone(), *date)) {
+            Some(prev) if prev != value => revisions.push(Revision {
+                metric: *metric,
+                jurisdiction: jurisdiction.clone(),
+                date: *date,
+                old: *prev,
+                new: *value,
+                removed: false,
+            }),
+            Some(_) => {}
+            None => {
+                let e = added.entry((*metric, jurisdiction)).or_insert((0, 0));
+                e.0 += 1;
+                e.1 += value.unwrap_or(0) as i64;
+            }
+        }
+    }
+    for ((metric, jurisdiction, date), prev) in &old {
+        if jurisdictions.contains(jurisdiction) && !new.contains_key(&(*metric, jurisdiction.clone(), *date)) {
+            revisions.push(Revision {
+                metric: *metric,
+                jurisdiction: jurisdiction.clone(),
+                date: *date,
+                old: *prev,
+                new: None,
+                removed: true,
+            });
+        }
+    }
+    revisions.sort_by(|a, b| (a.metric, &a.jurisdiction, a.date).cmp(&(b.metric, &b.jurisdiction, b.date)));
+
+    println!(
+        "Revisions from the {} vintage to the {} vintage",
+        vintage_date(&from),
+        to_date
+    );
+    for jurisdiction in &jurisdictions {
+        println!();
+        println!("## {jurisdiction}");
+        for metric in [Metric::Cases, Metric::Hospitalized, Metric::Icu] {
+            let revs: Vec<&Revision> = revisions
+                .iter()
+                .filter(|r| r.metric == metric && &r.jurisdiction == jurisdiction)
+                .collect();
+            let (new_dates, new_total) = added.get(&(metric, jurisdiction.as_str())).copied().unwrap_or((0, 0));
+            println!(
+                "{}: {:+} on {} new date(s), {:+} from revisions to {} earlier date(s)  ",
+                metric.name(),
+                new_total,
+                new_dates,
+                revs.iter().map(|r| r.delta()).sum::<i64>(),
+                revs.len()
+            );
+            for r in revs {
+                println!(
+                    "    {} ({:>3} days back): {} -> {} ({:+}){}",
+                    r.date,
+                    (to_date - r.date).num_days(),
+                    r.old.map_or("-".to_string(), |v| v.to_string()),
+                    r.new.map_or("-".to_string(), |v| v.to_string()),
+                    r.delta(),
+                    if r.removed { " date removed" } else { "" }
+                );
+            }
+        }
+    }
+
+    if let Err(e) = plot_revisions_by_lag(&revisions, to_date) {
+        println!("Error plotting revisions: {:?}", e);
+    }
+    Ok(())
+}
+
+/// Bar chart of total absolute revision by lag (days between the data date
+/// and the newer vintage), one bar per metric.
+fn plot_revisions_by_lag(revisions: &[Revision], to_date: chrono::NaiveDate) -> Result<()> {
+    let mut by_lag: BTreeMap<(Metric, i64), i64> = BTreeMap::new();
+    for r in revisions {
+        *by_lag.entry((r.metric, (to_date - r.date).num_days())).or_insert(0) += r.delta().abs();
+    }
+    let max_lag = by_lag.keys().map(|(_, lag)| *lag).max().unwrap_or(0).max(1);
+    let max_y = by_lag.values().copied().max().unwrap_or(0).max(1);
+
+    let root = BitMapBackend::new("revisions_by_lag.png", (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption(format!("Revisions by lag, vintage {to_date}"), ("sans-serif", 40))
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(-0.5f64..(max_lag as f64 + 0.5), 0i64..(max_y + max_y / 20))?;
+    chart
+        .configure_mesh()
+        .bold_line_style(BLACK.mix(0.10))
+        .light_line_style(BLACK.mix(0.05))
+        .x_label_formatter(&|x| format!("{:.0}", x))
+        .x_desc("Days between data date and vintage")
+        .y_desc("Total absolute revision")
+        .draw()?;
+    let metrics = [Metric::Cases, Metric::Hospitalized, Metric::Icu];
+    let width = 0.8 / metrics.len() as f64;
+    for (i, metric) in metrics.iter().enumerate() {
+        let style = plotters::style::ShapeStyle {
+            color: plotters::style::Palette99::pick(i).mix(0.8).to_rgba(),
+            filled: true,
+            stroke_width: 1,
+        };
+        let offset = -0.4 + width * i as f64;
+        chart
+            .draw_series(
+                by_lag
+                    .iter()
+                    .filter(|((m, _), _)| m == metric)
+                    .map(|((_, lag), total)| {
+                        let x = *lag as f64 + offset;
+                        Rectangle::new([(x, 0), (x + width, *total)], style.clone())
+                    }),
+            )?
+            .label(metric.name())
+            .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], style.clone()));
+    }
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperRight)
+        .draw()?;
+    Ok(())
+}
@@ -0,0 +1,288 @@
+//! Effective reproduction number, by the method of Cori et al. (2013).
+//!
+//! Each day's cases are compared with the infectiousness of the cases before
+//! it, weighted by the serial interval.  With a gamma prior on Rt and Poisson
+//! cases, the posterior over a sliding window is gamma too, so the estimate
+//! and credible interval come out in closed form.
+
+use anyhow::{bail, Result};
+use plotters::prelude::*;
+use serde::Deserialize;
+
+use crate::nowcast::Nowcast;
+use crate::HospitalRecord;
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RtOptions {
+    pub enabled: bool,
+    /// Mean and standard deviation of the serial interval, in days
+    pub si_mean: f64,
+    pub si_sd: f64,
+    /// Longest serial interval considered
+    pub si_max_days: usize,
+    /// Days in each estimation window
+    pub window: usize,
+}
+
+impl Default for RtOptions {
+    fn default() -> Self {
+        RtOptions {
+            enabled: true,
+            si_mean: 4.7,
+            si_sd: 2.9,
+            si_max_days: 20,
+            window: 7,
+        }
+    }
+}
+
+impl RtOptions {
+    /// A zero or negative spread, cutoff or window would divide by zero or
+    /// leave nothing to estimate from.
+    pub fn validate(&self) -> Result<()> {
+        for (name, value) in [("si_mean", self.si_mean), ("si_sd", self.si_sd)] {
+            if !value.is_finite() || value <= 0.0 {
+                bail!("rt.{name} must be positive, not {value}");
+            }
+        }
+        for (name, value) in [("si_max_days", self.si_max_days), ("window", self.window)] {
+            if value == 0 {
+                bail!("rt.{name} must be at least 1");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Prior on Rt: gamma with mean 5 and standard deviation 5, as in Cori et al.
+const PRIOR_SHAPE: f64 = 1.0;
+const PRIOR_SCALE: f64 = 5.0;
+/// Windows with fewer cases than this say more about the prior than the data
+const MIN_WINDOW_CASES: f64 = 12.0;
+/// Standard normal quantile for a 95% interval
+pub const Z_95: f64 = 1.959964;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RtPoint {
+    /// Last day of the window
+    pub date: chrono::NaiveDate,
+    pub mean: f64,
+    /// 95% credible interval
+    pub lo: f64,
+    pub hi: f64,
+}
+
+/// Serial interval distribution on days 1..=max: a gamma density with the
+/// configured mean and sd, evaluated at whole days and normalized.
+fn serial_interval(opts: &RtOptions) -> Vec<f64> {
+    let shape = (opts.si_mean / opts.si_sd).powi(2);
+    let scale = opts.si_sd.powi(2) / opts.si_mean;
+    let mut w: Vec<f64> = (0..=opts.si_max_days)
+        .map(|s| if s == 0 { 0.0 } else { (s as f64).powf(shape - 1.0) * (-(s as f64) / scale).exp() })
+        .collect();
+    let total: f64 = w.iter().sum();
+    w.iter_mut().for_each(|x| *x /= total);
+    w
+}
+
+/// Quantile of a gamma distribution, by the Wilson-Hilferty approximation.
+/// Good to well under a percent for the shapes seen here (dozens of cases
+/// and up).
+fn gamma_quantile(shape: f64, scale: f64, z: f64) -> f64 {
+    let c = 1.0 / (9.0 * shape);
+    (shape * (1.0 - c + z * c.sqrt()).max(0.0).powi(3)) * scale
+}
+
+/// Rt over time from one jurisdiction's date-sorted records.  The last record
+/// is left out as incomplete; if there's a nowcast, the recent days use its
+/// estimate instead of the undercounted observations.
+pub fn estimate(opts: &RtOptions, recs: &[HospitalRecord], nowcast: Option<&Nowcast>) -> Vec<RtPoint> {
+    let recs = &recs[..recs.len().saturating_sub(1)];
+    let incidence: Vec<f64> = recs
+        .iter()
+        .map(|r| {
+            let observed = r.new_cases.unwrap_or(0);
+            nowcast.map_or(observed as f64, |nc| nc.adjusted(r.date, observed).0)
+        })
+        .collect();
+    let w = serial_interval(opts);
+    // Total infectiousness of everyone infected before day t
+    let lambda: Vec<f64> = (0..incidence.len())
+        .map(|t| (1..w.len().min(t + 1)).map(|s| incidence[t - s] * w[s]).sum())
+        .collect();
+
+    let start = opts.si_max_days.max(opts.window);
+    (start..incidence.len())
+        .filter_map(|t| {
+            let cases: f64 = incidence[t + 1 - opts.window..=t].iter().sum();
+            let infectiousness: f64 = lambda[t + 1 - opts.window..=t].iter().sum();
+            if cases < MIN_WINDOW_CASES || infectiousness <= 0.0 {
+                return None;
+            }
+            let shape = PRIOR_SHAPE + cases;
+            let scale = 1.0 / (1.0 / PRIOR_SCALE + infectiousness);
+            Some(RtPoint {
+                date: recs[t].date,
+                mean: shape * scale,
+                lo: gamma_quantile(shape, scale, -Z_95),
+                hi: gamma_quantile(shape, scale, Z_95),
+            })
+        })
+        .collect()
+}
+
+/// Rt and its credible band since October 2020, with a line at 1.
+pub fn plot_rt(points: &[RtPoint], jurisdiction: &str) -> Result<()> {
+    let mut img_path = std::path::PathBuf::from(format!("{}_rt", str::replace(jurisdiction, " ", "_")));
+    img_path.set_extension("png");
+    let min_date = chrono::NaiveDate::from_ymd(2020, 10, 1);
+    let points: Vec<&RtPoint> = points.iter().filter(|p| p.date >= min_date).collect();
+    let max_date = match points.last() {
+        Some(p) => p.date + chrono::Duration::days(1),
+        None => return Err(anyhow::anyhow!("no Rt estimates to plot")),
+    };
+    let max_y = points.iter().map(|p| p.hi).fold(2.0, f64::max).min(4.0);
+
+    let root = BitMapBackend::new(&img_path, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let rt_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(1).mix(0.9).to_rgba(),
+        filled: true,
+        stroke_width: 2,
+    };
+    let band_style = plotters::style::ShapeStyle {
+        color: plotters::style::Palette99::pick(1).mix(0.2).to_rgba(),
+        filled: true,
+        stroke_width: 0,
+    };
+    let one_style = plotters::style::ShapeStyle {
+        color: BLACK.mix(0.6).to_rgba(),
+        filled: true,
+        stroke_width: 1,
+    };
+
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption(format!("Effective reproduction number: {jurisdiction}"), ("sans-serif", 40))
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .bold_line_style(BLACK.mix(0.10))
+        .light_line_style(BLACK.mix(0.05))
+        .x_labels(10)
+        .x_desc("Date")
+        .y_desc("Rt")
+        .draw()?;
+    let band: Vec<(chrono::NaiveDate, f64)> = points
+        .iter()
+        .map(|p| (p.date, p.hi.min(max_y)))
+        .chain(points.iter().rev().map(|p| (p.date, p.lo)))
+        .collect();
+    chart
+        .draw_series(std::iter::once(Polygon::new(band, band_style.clone())))?
+        .label("95% credible interval")
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 20, y + 5)], band_style.clone()));
+    chart
+        .draw_series(LineSeries::new(points.iter().map(|p| (p.date, p.mean.min(max_y))), rt_style.clone()))?
+        .label("Rt")
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], rt_style.clone()));
+    chart.draw_series(LineSeries::new(vec![(min_date, 1.0), (max_date, 1.0)], one_style))?;
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperLeft)
+        .draw()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Daily records with these case counts, plus the incomplete last day
+    /// `estimate` leaves out.
+    fn recs(cases: impl IntoIterator<Item = f64>) -> Vec<HospitalRecord> {
+        let first = chrono::NaiveDate::from_ymd(2021, 1, 1);
+        cases
+            .into_iter()
+            .chain([0.0])
+            .enumerate()
+            .map(|(i, c)| HospitalRecord {
+                county: "Allegheny".to_string(),
+                date: first + chrono::Duration::days(i as i64),
+                adult_icu_beds_available: None,
+                adult_icu_beds_total: None,
+                med_surg_available: None,
+                med_surg_total: None,
+                covid_hospitalized: None,
+                covid_ventilator: None,
+                covid_icu: None,
+                new_cases: Some(c.round() as u32),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn serial_interval_is_a_distribution() {
+        let opts = RtOptions::default();
+        let w = serial_interval(&opts);
+        assert_eq!(w.len(), opts.si_max_days + 1);
+        assert_eq!(w[0], 0.0);
+        assert!((w.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        let mean: f64 = w.iter().enumerate().map(|(s, p)| s as f64 * p).sum();
+        assert!((mean - opts.si_mean).abs() < 0.2, "{mean}");
+    }
+
+    #[test]
+    fn steady_cases_give_the_closed_form_posterior() {
+        let opts = RtOptions::default();
+        let points = estimate(&opts, &recs(std::iter::repeat_n(100.0, 60)), None);
+        // The first window starts once a full serial interval is behind it
+        assert_eq!(points.len(), 60 - opts.si_max_days);
+        let cases = 100.0 * opts.window as f64;
+        // Once every day of the window has a full serial interval behind it,
+        // infectiousness is 100 a day too
+        let shape = PRIOR_SHAPE + cases;
+        let scale = 1.0 / (1.0 / PRIOR_SCALE + cases);
+        for p in &points[opts.window - 1..] {
+            assert!((p.mean - shape * scale).abs() < 1e-9, "{p:?}");
+            assert!(p.lo < p.mean && p.mean < p.hi, "{p:?}");
+            assert!(p.lo > 0.9 && p.hi < 1.1, "{p:?}");
+        }
+    }
+
+    #[test]
+    fn growing_cases_match_the_euler_lotka_rt() {
+        let opts = RtOptions::default();
+        let r: f64 = 0.05;
+        let points = estimate(&opts, &recs((0..90).map(|t| 1000.0 * (r * t as f64).exp())), None);
+        let w = serial_interval(&opts);
+        let expected = 1.0 / w.iter().enumerate().map(|(s, p)| p * (-r * s as f64).exp()).sum::<f64>();
+        let last = points.last().unwrap();
+        assert!((last.mean - expected).abs() / expected < 0.01, "{} vs {expected}", last.mean);
+        assert!(last.lo > 1.0);
+    }
+
+    #[test]
+    fn skips_windows_with_few_cases() {
+        let points = estimate(&RtOptions::default(), &recs(std::iter::repeat_n(1.0, 60)), None);
+        assert!(points.is_empty());
+    }
+
+    #[test]
+    fn rejects_unusable_options() {
+        assert!(RtOptions::default().validate().is_ok());
+        for opts in [
+            RtOptions { si_sd: 0.0, ..RtOptions::default() },
+            RtOptions { si_sd: -1.0, ..RtOptions::default() },
+            RtOptions { si_max_days: 0, ..RtOptions::default() },
+            RtOptions { window: 0, ..RtOptions::default() },
+        ] {
+            assert!(opts.validate().is_err(), "{opts:?}");
+        }
+    }
+}
@@ -0,0 +1,222 @@
+//! Expected columns for each feed, plus the alias table that lets a renamed
+//! upstream header still land on the right field.
+//!
+//! Aliases come from the `[aliases.<kind>]` tables in the config, e.g.
+//!
+//! ```toml
+//! [aliases.hospitals]
+//! "COVID-19 Patients Hospitalized" = ["COVID-19 Hospitalized Patients"]
+//! ```
+//!
+//! Header matching ignores case and surrounding whitespace.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use crate::config::Config;
+
+/// Per-kind alias table: expected column -> other names it may appear under.
+pub type Aliases = HashMap<String, HashMap<String, Vec<String>>>;
+
+pub trait Schema {
+    /// Name of this feed in the config's alias tables and on the command line
+    const KIND: &'static str;
+    /// Column names the record type deserializes from
+    const COLUMNS: &'static [&'static str];
+    /// Date columns and their formats.  A bad date fails as a custom serde
+    /// error, which doesn't say which column it came from, so the parse
+    /// report finds it by checking these.
+    const DATES: &'static [(&'static str, &'static str)] = &[];
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim().eq_ignore_ascii_case(b.trim())
+}
+
+/// Which expected column (if any) a header in the file stands for.
+fn resolve(cfg: &Config, kind: &str, columns: &[&'static str], header: &str) -> Option<Resolved> {
+    if let Some(c) = columns.iter().find(|c| same_name(c, header)) {
+        return Some(Resolved { column: c, via_alias: *c != header });
+    }
+    let aliases = cfg.aliases.get(kind)?;
+    columns
+        .iter()
+        .find(|c| {
+            aliases
+                .get(**c)
+                .is_some_and(|names| names.iter().any(|n| same_name(n, header)))
+        })
+        .map(|c| Resolved { column: c, via_alias: true })
+}
+
+struct Resolved {
+    column: &'static str,
+    via_alias: bool,
+}
+
+/// Rewrite a file's header row so aliased columns carry the names `T` expects.
+pub fn canonical_headers<T: Schema>(cfg: &Config, headers: &csv::StringRecord) -> csv::StringRecord {
+    headers
+        .iter()
+        .map(|h| match resolve(cfg, T::KIND, T::COLUMNS, h) {
+            Some(r) => r.column,
+            None => h,
+        })
+        .collect()
+}
+
+struct Kind {
+    name: &'static str,
+    columns: &'static [&'static str],
+}
+
+fn kind<T: Schema>() -> Kind {
+    Kind {
+        name: T::KIND,
+        columns: T::COLUMNS,
+    }
+}
+
+fn all_kinds() -> Vec<Kind> {
+    vec![
+        kind::<crate::CasesRecord>(),
+        kind::<crate::HospitalRecord>(),
+        kind::<crate::PCR_tests>(),
+        kind::<crate::TestRecord>(),
+        kind::<crate::VaxDayRecord>(),
+        kind::<crate::VaxCoverageRecord>(),
+        kind::<crate::population::PopulationRecord>(),
+        kind::<crate::population::AgePopulationRecord>(),
+    ]
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SchemaCheckOpt {
+    #[structopt(long, help = "Feed to check against (default: best match)")]
+    kind: Option<String>,
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+}
+
+pub fn schema_check(cfg: &Config, opt: &SchemaCheckOpt) -> Result<()> {
+    let mut rdr = csv::Reader::from_path(&opt.file)?;
+    let headers = rdr.headers()?.clone();
+    let kinds = all_kinds();
+    // Share of the kind's expected columns that the file has
+    let coverage = |k: &Kind| {
+        let found = headers
+            .iter()
+            .filter(|h| resolve(cfg, k.name, k.columns, h).is_some())
+            .count();
+        found as f64 / k.columns.len() as f64
+    };
+    let kind = match &opt.kind {
+        Some(name) => kinds.iter().find(|k| k.name == name).ok_or_else(|| {
+            anyhow!(
+                "unknown kind {name:?}; expected one of {}",
+                kinds.iter().map(|k| k.name).collect::<Vec<_>>().join(", ")
+            )
+        })?,
+        None => kinds
+            .iter()
+            .max_by(|a, b| coverage(a).total_cmp(&coverage(b)))
+            .unwrap(),
+    };
+
+    println!("{}: checking against {} schema", opt.file.display(), kind.name);
+    let resolved: Vec<(&str, Option<Resolved>)> = headers
+        .iter()
+        .map(|h| (h, resolve(cfg, kind.name, kind.columns, h)))
+        .collect();
+    let mut missing = 0;
+    for column in kind.columns {
+        match resolved
+            .iter()
+            .find(|(_, r)| r.as_ref().is_some_and(|r| r.column == *column))
+        {
+            Some((h, Some(r))) if r.via_alias => println!("  present    {column:?} (as {h:?})"),
+            Some(_) => println!("  present    {column:?}"),
+            None => {
+                println!("  MISSING    {column:?}");
+                missing += 1;
+            }
+        }
+    }
+    for (h, _) in resolved.iter().filter(|(_, r)| r.is_none()) {
+        println!("  unexpected {h:?}");
+    }
+    if missing > 0 {
+        println!("{missing} expected column(s) missing; add an alias under [aliases.{}]", kind.name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_aliases(kind: &str, column: &str, names: &[&str]) -> Config {
+        let mut cfg = Config::default();
+        let names = names.iter().map(|n| n.to_string()).collect();
+        cfg.aliases
+            .entry(kind.to_string())
+            .or_default()
+            .insert(column.to_string(), names);
+        cfg
+    }
+
+    fn headers(names: &[&str]) -> csv::StringRecord {
+        csv::StringRecord::from(names.to_vec())
+    }
+
+    #[test]
+    fn exact_names_pass_through() {
+        let hs = headers(&["Jurisdiction", "Date", "New Cases"]);
+        assert_eq!(canonical_headers::<crate::CasesRecord>(&Config::default(), &hs), hs);
+    }
+
+    #[test]
+    fn matching_ignores_case_and_whitespace() {
+        let hs = headers(&[" jurisdiction", "DATE ", "new cases"]);
+        assert_eq!(
+            canonical_headers::<crate::CasesRecord>(&Config::default(), &hs),
+            headers(&["Jurisdiction", "Date", "New Cases"])
+        );
+    }
+
+    #[test]
+    fn aliases_map_to_the_expected_column() {
+        let cfg = with_aliases("cases", "New Cases", &["Cases (new)", "Daily Cases"]);
+        let hs = headers(&["Jurisdiction", "Date", "daily cases"]);
+        assert_eq!(
+            canonical_headers::<crate::CasesRecord>(&cfg, &hs),
+            headers(&["Jurisdiction", "Date", "New Cases"])
+        );
+    }
+
+    #[test]
+    fn aliases_only_apply_to_their_own_kind() {
+        let cfg = with_aliases("hospitals", "New Cases", &["Daily Cases"]);
+        let hs = headers(&["Jurisdiction", "Date", "Daily Cases"]);
+        assert_eq!(canonical_headers::<crate::CasesRecord>(&cfg, &hs), hs);
+    }
+
+    #[test]
+    fn unknown_headers_are_left_alone() {
+        let hs = headers(&["Jurisdiction", "Date", "New Cases", "Notes"]);
+        assert_eq!(canonical_headers::<crate::CasesRecord>(&Config::default(), &hs)[3], *"Notes");
+    }
+
+    #[test]
+    fn resolve_says_when_an_alias_was_used() {
+        let cfg = with_aliases("cases", "New Cases", &["Daily Cases"]);
+        let columns = crate::CasesRecord::COLUMNS;
+        assert!(!resolve(&cfg, "cases", columns, "Date").unwrap().via_alias);
+        assert!(resolve(&cfg, "cases", columns, "date").unwrap().via_alias);
+        let r = resolve(&cfg, "cases", columns, "Daily Cases").unwrap();
+        assert_eq!((r.column, r.via_alias), ("New Cases", true));
+        assert!(resolve(&cfg, "cases", columns, "Deaths").is_none());
+    }
+}
@@ -0,0 +1,199 @@
+//! How severe cases are, by age and over time.
+//!
+//! The test records flag cases that were hospitalized, admitted to the ICU
+//! or put on a ventilator.  Summed by month of report date, the flagged
+//! share of each age bucket's cases shows how severity shifted from wave to
+//! wave.  The flags are filled in as the county follows up, so the latest
+//! month or so reads low.
+
+use anyhow::{anyhow, Result};
+use chrono::Datelike;
+use plotters::prelude::*;
+use std::collections::HashMap;
+use structopt::StructOpt;
+
+use crate::breakdown::{Breakdowns, Field, Outcome};
+use crate::config::Config;
+use crate::table::{Cell, Format, Table};
+use crate::testrecs::summarize_tests;
+
+/// Key for the counts over every age bucket
+const ALL_AGES: &str = "all ages";
+
+/// Outcomes that make a case severe, in the order of the report's columns
+const SEVERE: [Outcome; 3] = [Outcome::Hospitalized, Outcome::Icu, Outcome::Ventilated];
+
+fn month_of(date: chrono::NaiveDate) -> chrono::NaiveDate {
+    chrono::NaiveDate::from_ymd(date.year(), date.month(), 1)
+}
+
+fn next_month(month: chrono::NaiveDate) -> chrono::NaiveDate {
+    match month.month() {
+        12 => chrono::NaiveDate::from_ymd(month.year() + 1, 1, 1),
+        m => chrono::NaiveDate::from_ymd(month.year(), m + 1, 1),
+    }
+}
+
+/// Cases and severe outcomes per age bucket and month of report date.
+#[derive(Debug)]
+pub struct Severity {
+    /// First day of each month, in order
+    pub months: Vec<chrono::NaiveDate>,
+    /// All ages first, then the buckets in sorted order
+    pub ages: Vec<String>,
+    counts: HashMap<(String, chrono::NaiveDate, Outcome), u32>,
+    /// Months with fewer cases than this in a bucket get no shares
+    min_cases: u32,
+}
+
+impl Severity {
+    pub fn from_breakdowns(groups: &Breakdowns, min_cases: u32) -> Option<Severity> {
+        let (first, last) = groups.get(Field::Age, Outcome::Cases).date_range()?;
+        let mut months = vec![month_of(first)];
+        while next_month(*months.last().unwrap()) <= last {
+            months.push(next_month(*months.last().unwrap()));
+        }
+        let mut ages = vec![ALL_AGES.to_string()];
+        let mut counts = HashMap::new();
+        for outcome in Outcome::ALL {
+            let tally = groups.get(Field::Age, outcome);
+            for age in tally.keys() {
+                if outcome == Outcome::Cases {
+                    ages.push(age.to_string());
+                }
+                for (i, n) in tally.series(age, first, last).into_iter().enumerate() {
+                    let month = month_of(first + chrono::Duration::days(i as i64));
+                    for key in [age, ALL_AGES] {
+                        *counts.entry((key.to_string(), month, outcome)).or_insert(0) += n;
+                    }
+                }
+            }
+        }
+        Some(Severity {
+            months,
+            ages,
+            counts,
+            min_cases,
+        })
+    }
+
+    pub fn count(&self, age: &str, month: chrono::NaiveDate, outcome: Outcome) -> u32 {
+        self.counts.get(&(age.to_string(), month, outcome)).copied().unwrap_or(0)
+    }
+
+    /// Percent of the month's cases in `age` with `outcome`, or None if there
+    /// were too few cases to say.
+    pub fn share(&self, age: &str, month: chrono::NaiveDate, outcome: Outcome) -> Option<f64> {
+        let cases = self.count(age, month, Outcome::Cases);
+        (cases >= self.min_cases.max(1)).then(|| self.count(age, month, outcome) as f64 * 100.0 / cases as f64)
+    }
+
+    /// One row per month and age bucket, all ages first.
+    pub fn table(&self) -> Table {
+        let mut table = Table::new(
+            "Severity of cases by age and month of report",
+            &["Month", "Group", "Cases", "Hospitalized %", "ICU %", "Ventilated %"],
+        );
+        for month in &self.months {
+            for age in &self.ages {
+                let cases = self.count(age, *month, Outcome::Cases);
+                if cases == 0 {
+                    continue;
+                }
+                let mut row = vec![Cell::text(month.format("%Y-%m").to_string()), Cell::text(age.as_str()), cases.into()];
+                row.extend(SEVERE.iter().map(|o| Cell::from(self.share(age, *month, *o))));
+                table.row(row);
+            }
+        }
+        table
+    }
+}
+
+/// Monthly share of cases with `outcome` per age bucket, to
+/// `<outcome>_share_by_age.png`.
+pub fn plot(severity: &Severity, outcome: Outcome) -> Result<()> {
+    let img_path = format!("{}_share_by_age.png", outcome.name());
+    let (min_date, max_date) = match (severity.months.first(), severity.months.last()) {
+        (Some(first), Some(last)) => (*first, next_month(*last)),
+        _ => return Err(anyhow!("no months to plot")),
+    };
+    let lines: Vec<(&str, Vec<(chrono::NaiveDate, f64)>)> = severity
+        .ages
+        .iter()
+        .map(|age| {
+            let points = severity
+                .months
+                .iter()
+                .filter_map(|m| Some((*m, severity.share(age, *m, outcome)?)))
+                .collect();
+            (age.as_str(), points)
+        })
+        .collect();
+    let max_y = lines
+        .iter()
+        .flat_map(|(_, points)| points.iter().map(|(_, v)| *v))
+        .fold(1.0, f64::max)
+        .min(100.0)
+        * 1.05;
+
+    let root = BitMapBackend::new(&img_path, (1024, 768)).into_drawing_area();
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .caption(
+            format!("{} as % of cases, by month: Allegheny County", outcome.label()),
+            ("sans-serif", 40),
+        )
+        .set_label_area_size(LabelAreaPosition::Left, 60)
+        .set_label_area_size(LabelAreaPosition::Right, 60)
+        .set_label_area_size(LabelAreaPosition::Bottom, 40)
+        .build_cartesian_2d(min_date..max_date, 0.0..max_y)?;
+    chart
+        .configure_mesh()
+        .x_labels(9)
+        .x_label_formatter(&|d| d.format("%Y-%m").to_string())
+        .x_desc("Month reported")
+        .y_desc("% of cases")
+        .draw()?;
+    for (color, (age, points)) in lines.into_iter().enumerate() {
+        // All ages stands out; the buckets are thinner
+        let style = plotters::style::ShapeStyle {
+            color: plotters::style::Palette99::pick(color).mix(if color == 0 { 0.9 } else { 0.6 }).to_rgba(),
+            filled: true,
+            stroke_width: if color == 0 { 3 } else { 1 },
+        };
+        chart
+            .draw_series(LineSeries::new(points, style.clone()))?
+            .label(age.to_owned())
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], style.clone()));
+    }
+    chart
+        .configure_series_labels()
+        .border_style(BLACK)
+        .position(SeriesLabelPosition::UpperRight)
+        .draw()?;
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SeverityOpt {
+    #[structopt(long, default_value = "2020-03-01", help = "First report date to include (%Y-%m-%d)")]
+    since: String,
+    #[structopt(long, default_value = "20", help = "Fewest cases in a month for an age group to get shares")]
+    min_cases: u32,
+    #[structopt(long, default_value = "text", help = "Table format: text, csv or json")]
+    format: Format,
+}
+
+/// Print the severity table and plot each outcome's share by age.
+pub fn severity(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &SeverityOpt) -> Result<()> {
+    let since = chrono::NaiveDate::parse_from_str(&opt.since, "%Y-%m-%d")?;
+    let summary = summarize_tests(cfg, today, since)?;
+    let severity = Severity::from_breakdowns(&summary.groups, opt.min_cases)
+        .ok_or_else(|| anyhow!("no cases in the test records since {since}"))?;
+    severity.table().print(opt.format)?;
+    for outcome in SEVERE {
+        plot(&severity, outcome)?;
+    }
+    Ok(())
+}
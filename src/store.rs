@@ -0,0 +1,239 @@
+//! SQLite store of daily snapshots ("vintages") of the cases and
+//! hospitalization feeds, keyed by (vintage date, jurisdiction, data date).
+//!
+//! `ingest` adds each day's vintage and `import-archive` backfills from the
+//! dated CSVs in `csv_dir`.  Reports read a vintage from here when it's
+//! present and fall back to the CSV archive when it isn't.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::parse::csvrecs;
+use crate::{CasesRecord, HospitalRecord};
+
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS cases (
+    vintage TEXT NOT NULL,
+    jurisdiction TEXT NOT NULL,
+    date TEXT NOT NULL,
+    new_cases INTEGER,
+    PRIMARY KEY (vintage, jurisdiction, date)
+);
+CREATE TABLE IF NOT EXISTS hospitals (
+    vintage TEXT NOT NULL,
+    jurisdiction TEXT NOT NULL,
+    date TEXT NOT NULL,
+    adult_icu_beds_available INTEGER,
+    adult_icu_beds_total INTEGER,
+    med_surg_available INTEGER,
+    med_surg_total INTEGER,
+    covid_hospitalized INTEGER,
+    covid_ventilator INTEGER,
+    covid_icu INTEGER,
+    PRIMARY KEY (vintage, jurisdiction, date)
+);
+";
+
+pub struct Store {
+    conn: Connection,
+}
+
+fn datestr(d: chrono::NaiveDate) -> String {
+    d.format(DATE_FORMAT).to_string()
+}
+
+fn parse_date(s: &str) -> rusqlite::Result<chrono::NaiveDate> {
+    chrono::NaiveDate::parse_from_str(s, DATE_FORMAT)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+impl Store {
+    /// Open the configured store, creating it if needed.
+    pub fn open(cfg: &Config) -> Result<Store> {
+        let path = cfg.store_path();
+        let conn = Connection::open(&path).with_context(|| format!("opening store {}", path.display()))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Store { conn })
+    }
+
+    /// Open the configured store only if it already exists.
+    pub fn open_existing(cfg: &Config) -> Result<Option<Store>> {
+        if cfg.store_path().is_file() {
+            Ok(Some(Store::open(cfg)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Replace the cases vintage for `vintage` with `recs`.
+    pub fn put_cases(&mut self, vintage: chrono::NaiveDate, recs: &[CasesRecord]) -> Result<()> {
+        let v = datestr(vintage);
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM cases WHERE vintage = ?1", params![v])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO cases (vintage, jurisdiction, date, new_cases) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for r in recs {
+                stmt.execute(params![v, r.county, datestr(r.date), r.new_cases])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace the hospitalization vintage for `vintage` with `recs`.
+    pub fn put_hospitals(&mut self, vintage: chrono::NaiveDate, recs: &[HospitalRecord]) -> Result<()> {
+        let v = datestr(vintage);
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM hospitals WHERE vintage = ?1", params![v])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO hospitals (vintage, jurisdiction, date,
+                    adult_icu_beds_available, adult_icu_beds_total, med_surg_available, med_surg_total,
+                    covid_hospitalized, covid_ventilator, covid_icu)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            )?;
+            for r in recs {
+                stmt.execute(params![
+                    v,
+                    r.county,
+                    datestr(r.date),
+                    r.adult_icu_beds_available,
+                    r.adult_icu_beds_total,
+                    r.med_surg_available,
+                    r.med_surg_total,
+                    r.covid_hospitalized,
+                    r.covid_ventilator,
+                    r.covid_icu,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn has_vintage(&self, table: &str, vintage: chrono::NaiveDate) -> Result<bool> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT 1 FROM {table} WHERE vintage = ?1 LIMIT 1"),
+                params![datestr(vintage)],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some())
+    }
+
+    /// The cases vintage for `vintage`, or None if it hasn't been stored.
+    pub fn cases(&self, vintage: chrono::NaiveDate) -> Result<Option<Vec<CasesRecord>>> {
+        if !self.has_vintage("cases", vintage)? {
+            return Ok(None);
+        }
+        let mut stmt = self
+            .conn
+            .prepare("SELECT jurisdiction, date, new_cases FROM cases WHERE vintage = ?1 ORDER BY jurisdiction, date")?;
+        let recs = stmt
+            .query_map(params![datestr(vintage)], |row| {
+                Ok(CasesRecord {
+                    county: row.get(0)?,
+                    date: parse_date(&row.get::<_, String>(1)?)?,
+                    new_cases: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Some(recs))
+    }
+
+    /// Sum of new cases for `jurisdiction` in the `vintage` cases snapshot,
+    /// or None if it hasn't been stored.
+    pub fn case_total(&self, vintage: chrono::NaiveDate, jurisdiction: &str) -> Result<Option<u32>> {
+        if !self.has_vintage("cases", vintage)? {
+            return Ok(None);
+        }
+        let total: i64 = self.conn.query_row(
+            "SELECT COALESCE(SUM(new_cases), 0) FROM cases WHERE vintage = ?1 AND jurisdiction = ?2",
+            params![datestr(vintage), jurisdiction],
+            |row| row.get(0),
+        )?;
+        Ok(Some(total as u32))
+    }
+
+    /// The hospitalization vintage for `vintage`, or None if it hasn't been stored.
+    pub fn hospitals(&self, vintage: chrono::NaiveDate) -> Result<Option<Vec<HospitalRecord>>> {
+        if !self.has_vintage("hospitals", vintage)? {
+            return Ok(None);
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT jurisdiction, date, adult_icu_beds_available, adult_icu_beds_total,
+                med_surg_available, med_surg_total, covid_hospitalized, covid_ventilator, covid_icu
+             FROM hospitals WHERE vintage = ?1 ORDER BY jurisdiction, date",
+        )?;
+        let recs = stmt
+            .query_map(params![datestr(vintage)], |row| {
+                Ok(HospitalRecord {
+                    county: row.get(0)?,
+                    date: parse_date(&row.get::<_, String>(1)?)?,
+                    adult_icu_beds_available: row.get(2)?,
+                    adult_icu_beds_total: row.get(3)?,
+                    med_surg_available: row.get(4)?,
+                    med_surg_total: row.get(5)?,
+                    covid_hospitalized: row.get(6)?,
+                    covid_ventilator: row.get(7)?,
+                    covid_icu: row.get(8)?,
+                    new_cases: None,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(Some(recs))
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ImportOpt {
+    #[structopt(long, parse(from_os_str), help = "Archive directory to import (default: csv_dir)")]
+    dir: Option<PathBuf>,
+    #[structopt(long, help = "Re-import vintages that are already in the store")]
+    force: bool,
+}
+
+/// Vintage date of an archived file, if its name matches `pattern`.
+fn vintage_of(path: &Path, pattern: &str) -> Option<chrono::NaiveDate> {
+    let name = path.file_name()?.to_str()?;
+    chrono::NaiveDate::parse_from_str(name, pattern).ok()
+}
+
+/// Backfill the store from the dated CSVs in the archive directory.
+pub fn import_archive(cfg: &Config, opt: &ImportOpt) -> Result<()> {
+    let dir = opt.dir.clone().unwrap_or_else(|| cfg.csv_dir.clone());
+    let mut store = Store::open(cfg)?;
+    let mut files: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .collect();
+    files.sort();
+    let (mut cases, mut hospitals) = (0, 0);
+    for path in files {
+        if let Some(vintage) = vintage_of(&path, &cfg.files.cases) {
+            if opt.force || !store.has_vintage("cases", vintage)? {
+                store.put_cases(vintage, &csvrecs::<CasesRecord>(cfg, &path)?)?;
+                cases += 1;
+            }
+        } else if let Some(vintage) = vintage_of(&path, &cfg.files.hospitalizations) {
+            if opt.force || !store.has_vintage("hospitals", vintage)? {
+                store.put_hospitals(vintage, &csvrecs::<HospitalRecord>(cfg, &path)?)?;
+                hospitals += 1;
+            }
+        }
+    }
+    println!(
+        "Imported {cases} cases and {hospitals} hospitalization vintages into {}",
+        cfg.store_path().display()
+    );
+    Ok(())
+}
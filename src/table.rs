@@ -0,0 +1,213 @@
+//! Small tables printed as aligned text, CSV or JSON.
+//!
+//! The text form goes into the daily thread; CSV and JSON are for pasting
+//! into a spreadsheet or feeding another script.
+
+use anyhow::{anyhow, Result};
+use serde::ser::{SerializeMap, Serializer};
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(anyhow!("unknown format {s:?}; expected text, csv or json")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Cell {
+    Text(String),
+    /// A number and how many decimals to show
+    Num(f64, usize),
+    Missing,
+}
+
+impl Cell {
+    pub fn text(s: impl Into<String>) -> Cell {
+        Cell::Text(s.into())
+    }
+
+    fn plain(&self) -> String {
+        match self {
+            Cell::Text(s) => s.clone(),
+            Cell::Num(v, decimals) => format!("{:.*}", decimals, v),
+            Cell::Missing => String::new(),
+        }
+    }
+}
+
+/// Numbers are rounded to the decimals shown, so JSON carries the same
+/// figures as the text and CSV; those shown without decimals go out as
+/// integers.  Missing cells and NaNs are null.
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self {
+            Cell::Text(s) => serializer.serialize_str(s),
+            Cell::Num(v, 0) if v.is_finite() => serializer.serialize_i64(v.round() as i64),
+            // Rounding through the shown text keeps ties the same as in print
+            Cell::Num(v, _) if v.is_finite() => match self.plain().parse() {
+                Ok(shown) => serializer.serialize_f64(shown),
+                Err(_) => serializer.serialize_f64(*v),
+            },
+            Cell::Num(..) | Cell::Missing => serializer.serialize_none(),
+        }
+    }
+}
+
+impl From<f64> for Cell {
+    fn from(v: f64) -> Cell {
+        Cell::Num(v, 1)
+    }
+}
+
+impl From<Option<f64>> for Cell {
+    fn from(v: Option<f64>) -> Cell {
+        v.map_or(Cell::Missing, Cell::from)
+    }
+}
+
+impl From<u32> for Cell {
+    fn from(v: u32) -> Cell {
+        Cell::Num(v as f64, 0)
+    }
+}
+
+/// A row as a JSON object, keyed by column in column order.
+struct JsonRow<'a> {
+    columns: &'a [String],
+    cells: &'a [Cell],
+}
+
+impl Serialize for JsonRow<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.columns.len()))?;
+        for (column, cell) in self.columns.iter().zip(self.cells) {
+            map.serialize_entry(column, cell)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Serialize)]
+struct JsonTable<'a> {
+    title: &'a str,
+    rows: Vec<JsonRow<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub title: String,
+    columns: Vec<String>,
+    rows: Vec<Vec<Cell>>,
+}
+
+impl Table {
+    pub fn new(title: impl Into<String>, columns: &[&str]) -> Table {
+        Table {
+            title: title.into(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn row(&mut self, cells: Vec<Cell>) {
+        self.rows.push(cells);
+    }
+
+    pub fn print(&self, format: Format) -> Result<()> {
+        match format {
+            Format::Text => self.print_text(),
+            Format::Csv => self.print_csv()?,
+            Format::Json => self.print_json()?,
+        }
+        Ok(())
+    }
+
+    /// Title line, then columns padded to their widest value.  The first
+    /// column is left-aligned, the rest right-aligned.
+    fn print_text(&self) {
+        let cells: Vec<Vec<String>> = self.rows.iter().map(|r| r.iter().map(Cell::plain).collect()).collect();
+        let widths: Vec<usize> = (0..self.columns.len())
+            .map(|i| {
+                cells
+                    .iter()
+                    .filter_map(|r| r.get(i))
+                    .map(|c| c.len())
+                    .chain([self.columns[i].len()])
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |vals: &[String]| {
+            vals.iter()
+                .zip(&widths)
+                .enumerate()
+                .map(|(i, (v, w))| if i == 0 { format!("{v:<w$}") } else { format!("{v:>w$}") })
+                .collect::<Vec<_>>()
+                .join("  ")
+        };
+        println!("{}  ", self.title);
+        println!();
+        println!("    {}", line(&self.columns));
+        for r in &cells {
+            println!("    {}", line(r));
+        }
+    }
+
+    fn print_csv(&self) -> Result<()> {
+        let mut w = csv::Writer::from_writer(std::io::stdout());
+        w.write_record(&self.columns)?;
+        for r in &self.rows {
+            w.write_record(r.iter().map(Cell::plain))?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    fn print_json(&self) -> Result<()> {
+        let table = JsonTable {
+            title: &self.title,
+            rows: self.rows.iter().map(|r| JsonRow { columns: &self.columns, cells: r }).collect(),
+        };
+        serde_json::to_writer_pretty(std::io::stdout(), &table)?;
+        println!();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(cell: Cell) -> String {
+        serde_json::to_string(&cell).unwrap()
+    }
+
+    #[test]
+    fn json_rounds_to_the_decimals_shown() {
+        assert_eq!(json(Cell::Num(12.345678, 1)), "12.3");
+        assert_eq!(json(Cell::Num(0.125, 2)), format!("{:.2}", 0.125));
+        assert_eq!(json(Cell::Num(2.6, 0)), "3");
+        assert_eq!(json(1234u32.into()), "1234");
+    }
+
+    #[test]
+    fn json_nulls_for_missing_and_nan() {
+        assert_eq!(json(Cell::Missing), "null");
+        assert_eq!(json(Cell::Num(f64::NAN, 1)), "null");
+        assert_eq!(json(Cell::text("x")), "\"x\"");
+    }
+}
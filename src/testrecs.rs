@@ -0,0 +1,100 @@
+//! Streaming aggregation of the WPRDC test records file.
+//!
+//! The file has one row per test and keeps growing, so rather than loading it
+//! into memory we fold each row into per-day counts as it is read.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::breakdown::Breakdowns;
+use crate::config::Config;
+use crate::parse::stream_csv;
+use crate::positivity::TestPositivity;
+use crate::reportlag::ReportLags;
+use crate::{tests_file, TestRecord};
+
+/// Per-day counts, broken down by some category of the record (age bucket, sex, ...).
+#[derive(Debug, Default)]
+pub struct Tally {
+    days: BTreeMap<chrono::NaiveDate, HashMap<String, u32>>,
+}
+
+impl Tally {
+    pub fn add(&mut self, date: chrono::NaiveDate, key: &str) {
+        let day = self.days.entry(date).or_default();
+        match day.get_mut(key) {
+            Some(n) => *n += 1,
+            None => {
+                day.insert(key.to_string(), 1);
+            }
+        }
+    }
+
+    /// First and last dates with any counts.
+    pub fn date_range(&self) -> Option<(chrono::NaiveDate, chrono::NaiveDate)> {
+        Some((*self.days.keys().next()?, *self.days.keys().next_back()?))
+    }
+
+    pub fn keys(&self) -> BTreeSet<&str> {
+        self.days
+            .values()
+            .flat_map(|day| day.keys().map(String::as_str))
+            .collect()
+    }
+
+    /// Daily counts for `key` from `from` through `to`, with zeros for days
+    /// that had none.
+    pub fn series(&self, key: &str, from: chrono::NaiveDate, to: chrono::NaiveDate) -> Vec<u32> {
+        let mut counts = vec![0; ((to - from).num_days() + 1).max(0) as usize];
+        for (date, day) in self.days.range(from..=to) {
+            counts[(*date - from).num_days() as usize] = day.get(key).copied().unwrap_or(0);
+        }
+        counts
+    }
+}
+
+/// Counts by report date of cases and their outcomes per demographic group,
+/// plus test positivity and reporting lags.
+#[derive(Debug, Default)]
+pub struct TestSummary {
+    pub groups: Breakdowns,
+    pub positivity: TestPositivity,
+    pub lags: ReportLags,
+}
+
+impl TestSummary {
+    fn add(&mut self, rec: &TestRecord) {
+        let date = rec.report_date;
+        self.positivity.add(rec);
+        self.lags.add(rec);
+        self.groups.add(date, rec);
+    }
+}
+
+/// Fold `f` over every test record in the day's WPRDC file without holding
+/// more than one row in memory.
+pub fn fold_test_records<F>(cfg: &Config, day: &chrono::DateTime<chrono::Local>, mut f: F) -> Result<()>
+where
+    F: FnMut(&TestRecord),
+{
+    stream_csv::<TestRecord, _>(cfg, tests_file(cfg, day), |row, headers| {
+        let rec: TestRecord = row.deserialize(Some(headers))?;
+        f(&rec);
+        Ok(())
+    })
+}
+
+/// Summarize the tests reported on or after `since`.
+pub fn summarize_tests(
+    cfg: &Config,
+    day: &chrono::DateTime<chrono::Local>,
+    since: chrono::NaiveDate,
+) -> Result<TestSummary> {
+    let mut summary = TestSummary::default();
+    fold_test_records(cfg, day, |rec| {
+        if rec.report_date >= since {
+            summary.add(rec);
+        }
+    })?;
+    Ok(summary)
+}
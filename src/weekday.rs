@@ -0,0 +1,165 @@
+//! Day-of-week reporting bias in the cases feed.
+//!
+//! Fewer cases get reported on weekends and more early in the week, so the
+//! raw daily series saw-tooths.  Each weekday's average share of its week's
+//! cases over recent weeks gives a factor to divide the daily counts by.
+
+use anyhow::{anyhow, Result};
+use chrono::Datelike;
+use itertools::Itertools;
+use serde::Deserialize;
+use structopt::StructOpt;
+
+use crate::config::Config;
+use crate::table::{Cell, Format, Table};
+use crate::store::Store;
+use crate::{day_of, get_all_records, HospitalRecord};
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WeekdayOptions {
+    pub enabled: bool,
+    /// How many weeks to average the weekday shares over
+    pub weeks: usize,
+}
+
+impl Default for WeekdayOptions {
+    fn default() -> Self {
+        WeekdayOptions { enabled: true, weeks: 16 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WeekdayEffect {
+    /// Average share of a week's cases reported on each weekday, Monday first
+    pub share: [f64; 7],
+    /// Share on each weekday in every week sampled, oldest first
+    pub weekly: Vec<[f64; 7]>,
+    /// Last date of the last week sampled
+    pub through: chrono::NaiveDate,
+}
+
+impl WeekdayEffect {
+    /// Estimate from the `weeks` full weeks before the last record of one
+    /// jurisdiction's date-sorted records.  Weeks with no cases are skipped;
+    /// None if there are none left.
+    pub fn estimate(recs: &[HospitalRecord], weeks: usize) -> Option<WeekdayEffect> {
+        let last = recs.len().checked_sub(1)?;
+        let start = last.checked_sub(weeks * 7)?;
+        let weekly: Vec<[f64; 7]> = recs[start..last]
+            .chunks(7)
+            .filter_map(|window| {
+                let total = window.iter().map(|r| r.new_cases.unwrap_or(0) as f64).sum::<f64>();
+                if total == 0.0 {
+                    return None;
+                }
+                let mut share = [0.0; 7];
+                for r in window {
+                    share[r.date.weekday().num_days_from_monday() as usize] += r.new_cases.unwrap_or(0) as f64 / total;
+                }
+                Some(share)
+            })
+            .collect();
+        if weekly.is_empty() {
+            return None;
+        }
+        let mut share = [0.0; 7];
+        for week in &weekly {
+            for (s, w) in share.iter_mut().zip(week) {
+                *s += w / weekly.len() as f64;
+            }
+        }
+        Some(WeekdayEffect {
+            share,
+            weekly,
+            through: recs[last - 1].date,
+        })
+    }
+
+    /// Sample variance of each weekday's share across the weeks sampled.
+    pub fn variance(&self) -> [f64; 7] {
+        let mut var = [0.0; 7];
+        if self.weekly.len() < 2 {
+            return var;
+        }
+        for week in &self.weekly {
+            for (i, v) in var.iter_mut().enumerate() {
+                *v += (week[i] - self.share[i]).powi(2) / (self.weekly.len() - 1) as f64;
+            }
+        }
+        var
+    }
+
+    /// How much `date`'s weekday over- or under-reports: 1.0 is an average day.
+    pub fn factor(&self, date: chrono::NaiveDate) -> f64 {
+        self.share[date.weekday().num_days_from_monday() as usize] * 7.0
+    }
+
+    /// `cases` reported on `date`, corrected for the weekday.
+    pub fn adjust(&self, date: chrono::NaiveDate, cases: u32) -> f64 {
+        let factor = self.factor(date);
+        if factor > 0.0 {
+            cases as f64 / factor
+        } else {
+            cases as f64
+        }
+    }
+
+    /// The weekday-adjusted daily series for `recs`.
+    pub fn adjusted_series(&self, recs: &[HospitalRecord]) -> Vec<(chrono::NaiveDate, f64)> {
+        recs.iter()
+            .map(|r| (r.date, self.adjust(r.date, r.new_cases.unwrap_or(0))))
+            .collect()
+    }
+}
+
+#[derive(Debug, StructOpt, Default)]
+pub struct DayreportOpt {
+    #[structopt(long, help = "Jurisdiction to analyze (default: the state)")]
+    jurisdiction: Option<String>,
+    #[structopt(long, help = "Number of weeks to average over (default: from config)")]
+    weeks: Option<usize>,
+    #[structopt(long, help = "Snapshot to analyze (%Y-%m-%d, default: --date or today)")]
+    as_of: Option<String>,
+    #[structopt(long, default_value = "text", help = "Output format: text, csv or json")]
+    format: Format,
+}
+
+/// Print each weekday's share of the weekly cases, and how much it varies
+/// from week to week.
+pub fn dayreport(cfg: &Config, today: &chrono::DateTime<chrono::Local>, opt: &DayreportOpt) -> Result<()> {
+    let as_of = match &opt.as_of {
+        Some(s) => day_of(chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d")?),
+        None => *today,
+    };
+    let jurisdiction = opt.jurisdiction.as_deref().unwrap_or(&cfg.state);
+    let weeks = opt.weeks.unwrap_or(cfg.weekday.weeks);
+    let recs: Vec<HospitalRecord> = get_all_records(cfg, Store::open_existing(cfg)?.as_ref(), &as_of)?
+        .into_iter()
+        .filter(|r| r.county == jurisdiction)
+        .sorted_by_key(|r| r.date)
+        .collect();
+    let effect = WeekdayEffect::estimate(&recs, weeks)
+        .ok_or_else(|| anyhow!("not enough records for {weeks} weeks of {jurisdiction}"))?;
+
+    let mut table = Table::new(
+        format!(
+            "Weekday shares of weekly cases, {jurisdiction}, {} weeks through {}",
+            effect.weekly.len(),
+            effect.through
+        ),
+        &["Weekday", "Share %", "Std dev %", "Variance", "Factor"],
+    );
+    for (i, var) in effect.variance().iter().enumerate() {
+        table.row(vec![
+            Cell::text(WEEKDAYS[i]),
+            Cell::Num(effect.share[i] * 100.0, 1),
+            Cell::Num(var.sqrt() * 100.0, 1),
+            Cell::Num(*var, 6),
+            Cell::Num(effect.share[i] * 7.0, 2),
+        ]);
+    }
+    table.print(opt.format)
+}